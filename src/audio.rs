@@ -0,0 +1,379 @@
+// ============================================================================
+// Audio Encoding - Whole-buffer and streaming encoders
+// ============================================================================
+//
+// `pcm` and `wav` are framed in-process. Other formats use a native encoder when one is
+// compiled in (see `encoders`), and otherwise an ffmpeg process, if ffmpeg is available.
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use std::process::{ExitStatus, Stdio};
+use std::sync::OnceLock;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::encoders::{native_encoder, NativeEncoder, NATIVE_FORMATS};
+use crate::metrics::METRICS;
+
+/// Bytes read from ffmpeg's stdout per body frame when streaming
+const FFMPEG_READ_SIZE: usize = 16 * 1024;
+
+/// Lines of ffmpeg's stderr quoted in errors
+const FFMPEG_STDERR_LINES: usize = 5;
+
+/// ffmpeg executable, set once at startup; `None` when it is disabled or missing
+static FFMPEG: OnceLock<Option<String>> = OnceLock::new();
+
+/// Item produced by the synthesis side of a streaming response
+pub type PcmChunk = Result<Vec<f32>>;
+
+/// Item consumed by the HTTP body of a streaming response
+pub type EncodedChunk = std::result::Result<Bytes, std::io::Error>;
+
+/// Values accepted for `response_format`
+pub const SUPPORTED_FORMATS: &[&str] = &["mp3", "opus", "aac", "flac", "wav", "pcm"];
+
+pub fn determine_content_type(format: &str) -> String {
+    match format {
+        "mp3" => "audio/mpeg",
+        "opus" => "audio/opus",
+        "aac" => "audio/aac",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "pcm" => "audio/pcm",
+        _ => "application/octet-stream",
+    }
+    .to_string()
+}
+
+pub fn sample_to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * 32767.0) as i16
+}
+
+/// Convert f32 samples to 16-bit little-endian PCM
+pub fn samples_to_pcm16(samples: &[f32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(samples.len() * 2);
+    for &sample in samples {
+        bytes.extend_from_slice(&sample_to_i16(sample).to_le_bytes());
+    }
+    bytes
+}
+
+/// Probe the ffmpeg executable once at startup; an empty path disables ffmpeg
+pub fn init_ffmpeg(path: &str) {
+    let ffmpeg = if path.is_empty() {
+        None
+    } else {
+        match std::process::Command::new(path).arg("-version").stdout(Stdio::null()).stderr(Stdio::null()).status() {
+            Ok(status) if status.success() => Some(path.to_string()),
+            Ok(status) => {
+                warn!("{} -version exited with {}; ffmpeg fallback disabled", path, status);
+                None
+            }
+            Err(e) => {
+                warn!("ffmpeg not found at '{}' ({}); ffmpeg fallback disabled", path, e);
+                None
+            }
+        }
+    };
+
+    let encoder = |format: &&str| match (NATIVE_FORMATS.contains(format), &ffmpeg) {
+        (true, _) => format!("{} (native)", format),
+        (false, Some(_)) => format!("{} (ffmpeg)", format),
+        (false, None) => format!("{} (unavailable)", format),
+    };
+    let encoders: Vec<String> = SUPPORTED_FORMATS.iter().filter(|f| !["pcm", "wav"].contains(f)).map(encoder).collect();
+    info!("Audio encoders: {}", encoders.join(", "));
+
+    let _ = FFMPEG.set(ffmpeg);
+}
+
+fn ffmpeg_path() -> Option<&'static str> {
+    FFMPEG.get().and_then(|path| path.as_deref())
+}
+
+/// Why `format` cannot be produced by this server, if it cannot
+pub fn check_format(format: &str) -> Result<(), String> {
+    if !SUPPORTED_FORMATS.contains(&format) {
+        return Err(format!("Invalid response_format: {}. Supported: {}", format, SUPPORTED_FORMATS.join(", ")));
+    }
+    if format == "pcm" || format == "wav" || NATIVE_FORMATS.contains(&format) || ffmpeg_path().is_some() {
+        return Ok(());
+    }
+    Err(format!("response_format {} needs ffmpeg, which is not available on this server", format))
+}
+
+pub async fn convert_audio(samples: &[f32], sample_rate: i32, format: &str) -> Result<Vec<u8>> {
+    if format == "pcm" {
+        return Ok(samples_to_pcm16(samples));
+    }
+
+    if format == "wav" {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: sample_rate as u32,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+        for &sample in samples {
+            writer.write_sample(sample_to_i16(sample))?;
+        }
+        writer.finalize()?;
+        return Ok(cursor.into_inner());
+    }
+
+    if let Some(encoder) = native_encoder(format, sample_rate) {
+        let mut encoder = encoder?;
+        let samples = samples.to_vec();
+        return tokio::task::spawn_blocking(move || {
+            let mut bytes = encoder.encode(&samples)?;
+            bytes.extend(encoder.finish()?);
+            Ok(bytes)
+        })
+        .await?;
+    }
+
+    let mut child = spawn_ffmpeg(sample_rate, format)?;
+
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow::anyhow!("Failed to open stdin"))?;
+
+    let pcm_bytes = samples_to_pcm16(samples);
+
+    tokio::spawn(async move {
+        let _ = stdin.write_all(&pcm_bytes).await;
+    });
+
+    let output = child.wait_with_output().await?;
+
+    if !output.status.success() {
+        return Err(ffmpeg_failed(output.status, &output.stderr));
+    }
+
+    Ok(output.stdout)
+}
+
+fn spawn_ffmpeg(sample_rate: i32, format: &str) -> Result<tokio::process::Child> {
+    let path = ffmpeg_path().ok_or_else(|| anyhow!("ffmpeg is not available to encode {}", format))?;
+    let mut cmd = Command::new(path);
+    cmd.args([
+        "-hide_banner",
+        "-loglevel", "error",
+        "-f", "s16le",
+        "-ar", &sample_rate.to_string(),
+        "-ac", "1",
+        "-i", "pipe:0",
+        "-f", format,
+        "pipe:1"
+    ]);
+
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    cmd.kill_on_drop(true);
+
+    cmd.spawn().map_err(|e| {
+        METRICS.ffmpeg_failures.inc();
+        anyhow!("Failed to start ffmpeg: {}", e)
+    })
+}
+
+/// Count and log a failed ffmpeg run, quoting the end of its stderr in the error
+fn ffmpeg_failed(status: ExitStatus, stderr: &[u8]) -> anyhow::Error {
+    METRICS.ffmpeg_failures.inc();
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let tail = lines[lines.len().saturating_sub(FFMPEG_STDERR_LINES)..].join("; ");
+    error!("ffmpeg exited with {}: {}", status, tail);
+    if tail.is_empty() {
+        anyhow!("ffmpeg exited with {}", status)
+    } else {
+        anyhow!("ffmpeg exited with {}: {}", status, tail)
+    }
+}
+
+// ============================================================================
+// Streaming
+// ============================================================================
+
+/// WAV header for a stream of unknown length.
+///
+/// The RIFF and data sizes are set to `0xFFFFFFFF`, which players treat as
+/// "read until EOF". Use [`finalize_wav_stream`] to patch them once the full
+/// stream is known.
+pub fn wav_stream_header(sample_rate: i32) -> Vec<u8> {
+    let sample_rate = sample_rate as u32;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // mono
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // byte rate
+    header.extend_from_slice(&2u16.to_le_bytes()); // block align
+    header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header
+}
+
+/// Patch the placeholder sizes of a fully buffered streamed WAV file
+pub fn finalize_wav_stream(bytes: &mut [u8]) {
+    if bytes.len() < 44 {
+        return;
+    }
+    let data_len = (bytes.len() - 44) as u32;
+    bytes[4..8].copy_from_slice(&(data_len + 36).to_le_bytes());
+    bytes[40..44].copy_from_slice(&data_len.to_le_bytes());
+}
+
+/// Encode PCM chunks into the requested format as they arrive.
+///
+/// `pcm` and `wav` are framed in-process, native encoders run on a blocking thread,
+/// and any other format is piped through a single long-lived ffmpeg process. An error
+/// on the PCM side, or a failing encoder, ends the returned stream with an `Err` item.
+pub fn encode_stream(
+    mut pcm_rx: mpsc::Receiver<PcmChunk>,
+    sample_rate: i32,
+    format: &str,
+) -> mpsc::Receiver<EncodedChunk> {
+    let (tx, rx) = mpsc::channel::<EncodedChunk>(16);
+
+    if format == "pcm" || format == "wav" {
+        let with_header = format == "wav";
+        tokio::spawn(async move {
+            if with_header && tx.send(Ok(Bytes::from(wav_stream_header(sample_rate)))).await.is_err() {
+                return;
+            }
+            while let Some(chunk) = pcm_rx.recv().await {
+                let item = chunk
+                    .map(|samples| Bytes::from(samples_to_pcm16(&samples)))
+                    .map_err(to_io_error);
+                let failed = item.is_err();
+                if tx.send(item).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        return rx;
+    }
+
+    if let Some(encoder) = native_encoder(format, sample_rate) {
+        match encoder {
+            Ok(encoder) => {
+                tokio::task::spawn_blocking(move || encode_native_stream(encoder, pcm_rx, tx));
+            }
+            Err(e) => {
+                let _ = tx.try_send(Err(to_io_error(e)));
+            }
+        }
+        return rx;
+    }
+
+    let mut child = match spawn_ffmpeg(sample_rate, format) {
+        Ok(child) => child,
+        Err(e) => {
+            let _ = tx.try_send(Err(to_io_error(e)));
+            return rx;
+        }
+    };
+    let (Some(mut stdin), Some(mut stdout), Some(mut stderr)) = (child.stdin.take(), child.stdout.take(), child.stderr.take()) else {
+        let _ = tx.try_send(Err(to_io_error(anyhow!("Failed to open ffmpeg pipes"))));
+        return rx;
+    };
+
+    // Drain stderr so ffmpeg never blocks on it; it is quoted if ffmpeg fails
+    let stderr_task = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf).await;
+        buf
+    });
+
+    // Feed PCM into ffmpeg; closing stdin lets ffmpeg flush and exit.
+    let err_tx = tx.clone();
+    tokio::spawn(async move {
+        while let Some(chunk) = pcm_rx.recv().await {
+            match chunk {
+                Ok(samples) => {
+                    if stdin.write_all(&samples_to_pcm16(&samples)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = err_tx.send(Err(to_io_error(e))).await;
+                    return;
+                }
+            }
+        }
+    });
+
+    // Forward encoded output as it becomes available.
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; FFMPEG_READ_SIZE];
+        loop {
+            match stdout.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.send(Ok(Bytes::copy_from_slice(&buf[..n]))).await.is_err() {
+                        let _ = child.kill().await;
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    let _ = child.kill().await;
+                    return;
+                }
+            }
+        }
+
+        match child.wait().await {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                let stderr = stderr_task.await.unwrap_or_default();
+                let _ = tx.send(Err(to_io_error(ffmpeg_failed(status, &stderr)))).await;
+            }
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Run a native encoder over the stream on the current (blocking) thread
+fn encode_native_stream(
+    mut encoder: Box<dyn NativeEncoder>,
+    mut pcm_rx: mpsc::Receiver<PcmChunk>,
+    tx: mpsc::Sender<EncodedChunk>,
+) {
+    while let Some(chunk) = pcm_rx.blocking_recv() {
+        let item = chunk.and_then(|samples| encoder.encode(&samples));
+        match item {
+            Ok(bytes) if bytes.is_empty() => {}
+            Ok(bytes) => {
+                if tx.blocking_send(Ok(Bytes::from(bytes))).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let _ = tx.blocking_send(Err(to_io_error(e)));
+                return;
+            }
+        }
+    }
+
+    let item = encoder.finish().map(Bytes::from).map_err(to_io_error);
+    let _ = tx.blocking_send(item);
+}
+
+fn to_io_error(e: anyhow::Error) -> std::io::Error {
+    std::io::Error::other(e.to_string())
+}
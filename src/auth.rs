@@ -0,0 +1,229 @@
+// ============================================================================
+// Authentication - Bearer API keys with per-key scopes
+// ============================================================================
+//
+// Key entries (key file lines, or `;`-separated in SUPERTONIC_API_KEYS):
+//   <key> [<scope>[,<scope>...]] [<name>]
+//
+// `<key>` is either the key itself or `sha256:<hex digest>` so the file need not hold
+// plaintext keys. Scopes default to `synthesize`. Keys are only kept as digests in memory.
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::openai_error;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// Speech synthesis over HTTP and WebSocket
+    Synthesize,
+    /// Voice and cache management
+    Admin,
+}
+
+impl Scope {
+    fn parse(scope: &str) -> Result<Self> {
+        match scope {
+            "synthesize" => Ok(Scope::Synthesize),
+            "admin" => Ok(Scope::Admin),
+            other => bail!("Unknown scope '{}'; expected synthesize or admin", other),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Synthesize => "synthesize",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    /// Identifies the key in logs and rate limits; never the key itself
+    pub name: String,
+    scopes: Vec<Scope>,
+}
+
+/// Authenticated key name, inserted into the request extensions by [`authorize`]
+#[derive(Clone, Debug)]
+pub struct ApiKeyId(pub String);
+
+/// Configured keys, indexed by the SHA-256 digest of the key
+#[derive(Default, Debug)]
+pub struct ApiKeys {
+    keys: HashMap<[u8; 32], ApiKey>,
+}
+
+impl ApiKeys {
+    /// Authentication is only enforced once at least one key is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether a configured key is named `name`
+    pub fn has_name(&self, name: &str) -> bool {
+        self.keys.values().any(|key| key.name == name)
+    }
+
+    /// Add one key entry in the `<key> [scopes] [name]` format
+    pub fn add_entry(&mut self, entry: &str) -> Result<()> {
+        let mut fields = entry.split_whitespace();
+        let Some(key) = fields.next() else {
+            bail!("Empty API key entry");
+        };
+        let scopes = match fields.next() {
+            Some(scopes) => scopes.split(',').map(Scope::parse).collect::<Result<Vec<_>>>()?,
+            None => vec![Scope::Synthesize],
+        };
+        let name = fields.next().map(str::to_string);
+        if fields.next().is_some() {
+            bail!("Unexpected trailing fields in API key entry");
+        }
+
+        let digest = match key.strip_prefix("sha256:") {
+            Some(hex_digest) => {
+                let bytes = hex::decode(hex_digest).context("Invalid sha256 key digest")?;
+                <[u8; 32]>::try_from(bytes.as_slice())
+                    .map_err(|_| anyhow::anyhow!("sha256 key digest must be 64 hex characters"))?
+            }
+            None => digest_key(key),
+        };
+        let name = name.unwrap_or_else(|| format!("key-{}", &hex::encode(digest)[..8]));
+
+        if self.keys.insert(digest, ApiKey { name, scopes }).is_some() {
+            bail!("Duplicate API key");
+        }
+        Ok(())
+    }
+
+    /// Load a key file; blank lines and `#` comments are skipped
+    pub fn add_file(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read API key file {}", path.display()))?;
+        for (i, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            self.add_entry(line)
+                .with_context(|| format!("{}:{}", path.display(), i + 1))?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, key: &str) -> Option<&ApiKey> {
+        self.keys.get(&digest_key(key))
+    }
+}
+
+/// Cut a `#` comment, which starts a line or follows whitespace; a `#` inside a key is kept
+fn strip_comment(line: &str) -> &str {
+    let mut previous = None;
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_none_or(char::is_whitespace) {
+            return &line[..i];
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+fn digest_key(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// State for one [`authorize`] layer: the keys plus the scope its routes need
+#[derive(Clone)]
+pub struct ScopeGuard {
+    keys: Arc<ApiKeys>,
+    /// `None` accepts any valid key
+    scope: Option<Scope>,
+}
+
+impl ScopeGuard {
+    pub fn new(keys: Arc<ApiKeys>, scope: Option<Scope>) -> Self {
+        ScopeGuard { keys, scope }
+    }
+}
+
+/// Middleware checking the bearer key against the guard's scope.
+///
+/// Without configured keys everything is allowed except admin routes, which stay disabled.
+pub async fn authorize(State(guard): State<ScopeGuard>, mut request: Request, next: Next) -> Response {
+    if !guard.keys.is_enabled() {
+        if guard.scope == Some(Scope::Admin) {
+            return openai_error(
+                StatusCode::FORBIDDEN,
+                "Admin endpoints are disabled; configure an API key with the admin scope to enable them",
+                "invalid_request_error",
+                None,
+                None,
+            );
+        }
+        return next.run(request).await;
+    }
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+
+    let Some(provided) = provided.filter(|key| !key.is_empty()) else {
+        return unauthorized(
+            "You didn't provide an API key. You need to provide your API key in an Authorization header using Bearer auth (i.e. Authorization: Bearer YOUR_KEY).",
+            None,
+        );
+    };
+
+    let Some(key) = guard.keys.lookup(provided) else {
+        return unauthorized(
+            &format!("Incorrect API key provided: {}", redact_key(provided)),
+            Some("invalid_api_key"),
+        );
+    };
+
+    if let Some(scope) = guard.scope {
+        if !key.scopes.contains(&scope) {
+            return unauthorized(
+                &format!("You have insufficient permissions for this operation. Missing scopes: {}", scope.as_str()),
+                Some("insufficient_permissions"),
+            );
+        }
+    }
+
+    debug!("Authorized {} {} with key '{}'", request.method(), request.uri().path(), key.name);
+    request.extensions_mut().insert(ApiKeyId(key.name.clone()));
+    next.run(request).await
+}
+
+fn unauthorized(message: &str, code: Option<&str>) -> Response {
+    openai_error(StatusCode::UNAUTHORIZED, message, "invalid_request_error", None, code)
+}
+
+/// Show only the ends of a rejected key, like OpenAI does
+fn redact_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}{}{}", head, "*".repeat(chars.len() - 7), tail)
+}
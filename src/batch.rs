@@ -0,0 +1,142 @@
+// ============================================================================
+// Batching - Run chunks from concurrent requests through the models together
+// ============================================================================
+//
+// The dispatcher waits for a first chunk, keeps collecting until the batch window
+// closes or the batch is full, then hands each group of chunks sharing `total_step`
+// to a pool engine as one batch. Rows may use different voices and speeds.
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+use crate::helper::{BatchRow, CancelToken, Style};
+use crate::metrics::METRICS;
+use crate::pool::EnginePool;
+use crate::queue::Priority;
+
+/// One chunk of text to synthesize
+pub struct ChunkJob {
+    pub text: String,
+    pub lang: String,
+    pub style: Arc<Style>,
+    pub total_step: usize,
+    pub speed: f32,
+    pub seed: u64,
+    pub priority: Priority,
+    /// Set once the requester no longer wants the audio
+    pub cancel: CancelToken,
+}
+
+struct Pending {
+    job: ChunkJob,
+    reply: oneshot::Sender<Result<(Vec<f32>, f32)>>,
+}
+
+pub struct Batcher {
+    tx: mpsc::UnboundedSender<Pending>,
+    max_batch_size: usize,
+}
+
+impl Batcher {
+    pub fn start(engines: Arc<EnginePool>, window: Duration, max_batch_size: usize) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(dispatch(engines, rx, window, max_batch_size));
+        Batcher { tx, max_batch_size }
+    }
+
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+
+    /// Queue a chunk and wait for its audio, trimmed to its predicted duration
+    pub async fn synthesize(&self, job: ChunkJob) -> Result<(Vec<f32>, f32)> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Pending { job, reply })
+            .map_err(|_| anyhow!("Batch dispatcher has stopped"))?;
+        rx.await.map_err(|_| anyhow!("Batch was dropped before completing"))?
+    }
+}
+
+async fn dispatch(
+    engines: Arc<EnginePool>,
+    mut rx: mpsc::UnboundedReceiver<Pending>,
+    window: Duration,
+    max_batch_size: usize,
+) {
+    while let Some(first) = rx.recv().await {
+        let mut pending = vec![first];
+        let deadline = tokio::time::Instant::now() + window;
+        while pending.len() < max_batch_size {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(next)) => pending.push(next),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        // The denoising loop runs `total_step` times for the whole batch, so rows must agree on it
+        let mut groups: Vec<Vec<Pending>> = Vec::new();
+        for item in pending {
+            match groups.iter_mut().find(|g| g[0].job.total_step == item.job.total_step) {
+                Some(group) => group.push(item),
+                None => groups.push(vec![item]),
+            }
+        }
+
+        for group in groups {
+            let engines = engines.clone();
+            tokio::task::spawn_blocking(move || run_batch(&engines, group));
+        }
+    }
+}
+
+fn run_batch(engines: &EnginePool, mut batch: Vec<Pending>) {
+    // Skip chunks whose request has already gone away
+    batch.retain(|item| !item.reply.is_closed() && !item.job.cancel.is_cancelled());
+    if batch.is_empty() {
+        return;
+    }
+
+    let total_step = batch[0].job.total_step;
+    let rows: Vec<BatchRow> = batch
+        .iter()
+        .map(|item| BatchRow {
+            text: &item.job.text,
+            lang: &item.job.lang,
+            style: &item.job.style,
+            speed: item.job.speed,
+            seed: item.job.seed,
+        })
+        .collect();
+
+    // The batch waits for an engine with the priority of its most urgent row
+    let priority = batch.iter().map(|item| item.job.priority).min().unwrap_or_default();
+    let mut tts = engines.checkout(priority);
+    let started = Instant::now();
+    // Stop early only when every row has been given up on
+    let cancelled = || batch.iter().all(|item| item.reply.is_closed() || item.job.cancel.is_cancelled());
+    let result = tts.synthesize_batch(&rows, total_step, &cancelled);
+    drop(tts);
+    METRICS.batch_size.observe(batch.len() as f64);
+
+    match result {
+        Ok(outputs) => {
+            let audio_secs: f32 = outputs.iter().map(|(_, dur)| dur).sum();
+            let chars: usize = batch.iter().map(|item| item.job.text.chars().count()).sum();
+            engines.observe_synthesis(started.elapsed(), audio_secs, chars);
+
+            for (item, output) in batch.into_iter().zip(outputs) {
+                let _ = item.reply.send(Ok(output));
+            }
+        }
+        Err(e) => {
+            error!("Batch of {} chunks failed: {}", batch.len(), e);
+            for item in batch {
+                let _ = item.reply.send(Err(anyhow!("{}", e)));
+            }
+        }
+    }
+}
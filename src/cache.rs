@@ -0,0 +1,1026 @@
+// ============================================================================
+// Audio Cache - Indexed disk entries behind a size-bounded in-memory LRU
+// ============================================================================
+//
+// Disk entries are the encoded audio behind a small header holding its SHA-256, so a
+// truncated or corrupted file is detected on read instead of being served. Entries are
+// written to a temporary file and renamed into place, so readers never see partial files.
+//
+// Synthesized audio is kept as canonical f32 PCM, one entry per request without its format.
+// Encoded outputs derived from it form a second tier: they make repeat requests cheap,
+// but are evicted first since they can be re-encoded from the PCM without resynthesis.
+//
+// `index.jsonl` in the cache directory records what each entry was generated from, its
+// size, hit count and last access, one JSON object per line. It is rewritten periodically
+// and reconciled with the directory on startup, so a crash loses at most recent hit counts.
+
+use anyhow::{Context, Result};
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+use crate::metrics::METRICS;
+use crate::{openai_error, AppState};
+
+/// Marks a cache file and its layout version
+const MAGIC: &[u8; 8] = b"STTSC\0\0\x01";
+const HEADER_LEN: usize = MAGIC.len() + 32;
+
+const INDEX_FILE: &str = "index.jsonl";
+
+/// `format` of canonical PCM entries: little-endian f32 samples
+pub const PCM_FORMAT: &str = "f32";
+
+/// How often a changed index is written back to disk
+const INDEX_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Entries returned by a listing unless the request asks for another number
+const DEFAULT_LIST_LIMIT: usize = 100;
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// ============================================================================
+// Disk Entries
+// ============================================================================
+
+/// Read a disk entry, returning its audio only if the checksum matches.
+/// Missing files give `None`; corrupt ones are also removed so they get regenerated.
+async fn read_verified(path: &Path) -> Option<Bytes> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => Bytes::from(bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to read cache file {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let valid = bytes.len() >= HEADER_LEN
+        && bytes.starts_with(MAGIC)
+        && Sha256::digest(&bytes[HEADER_LEN..]).as_slice() == &bytes[MAGIC.len()..HEADER_LEN];
+    if !valid {
+        warn!("Removing corrupt cache file {}", path.display());
+        METRICS.cache_corrupt.inc();
+        let _ = tokio::fs::remove_file(path).await;
+        return None;
+    }
+    Some(bytes.slice(HEADER_LEN..))
+}
+
+/// Write a disk entry, prefixed with the header `read_verified` checks
+async fn write_verified(path: &Path, audio: &[u8]) -> Result<()> {
+    let mut contents = Vec::with_capacity(HEADER_LEN + audio.len());
+    contents.extend_from_slice(MAGIC);
+    contents.extend_from_slice(&Sha256::digest(audio));
+    contents.extend_from_slice(audio);
+    write_atomic(path, &contents).await
+}
+
+/// Write through a temporary file renamed into place
+async fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path.file_name().context("Cache path has no file name")?.to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.{:016x}.tmp", file_name, rand::random::<u64>()));
+    if let Err(e) = tokio::fs::write(&tmp_path, contents).await {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e).with_context(|| format!("Failed to write {}", tmp_path.display()));
+    }
+    if let Err(e) = tokio::fs::rename(&tmp_path, path).await {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e).with_context(|| format!("Failed to move cache file into place at {}", path.display()));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Index
+// ============================================================================
+
+/// Request fields a cache entry was generated from
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EntryKey {
+    pub input: String,
+    pub voice: String,
+    pub format: String,
+    pub lang: String,
+    pub speed: f32,
+    pub total_step: usize,
+    pub seed: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// File stem of the entry in the cache directory
+    pub hash: String,
+    #[serde(flatten)]
+    pub key: EntryKey,
+    pub size: u64,
+    pub hits: u64,
+    /// Unix seconds
+    pub created: u64,
+    /// Unix seconds of the last time the entry was written or served
+    pub last_access: u64,
+}
+
+impl IndexEntry {
+    fn file_name(&self) -> String {
+        format!("{}.{}", self.hash, self.key.format)
+    }
+
+    fn is_pcm(&self) -> bool {
+        self.key.format == PCM_FORMAT
+    }
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, IndexEntry>,
+    size: u64,
+    /// Changed since the last flush
+    dirty: bool,
+}
+
+impl Index {
+    fn insert(&mut self, entry: IndexEntry) {
+        self.size += entry.size;
+        if let Some(old) = self.entries.insert(entry.hash.clone(), entry) {
+            self.size -= old.size;
+        }
+        self.dirty = true;
+    }
+
+    fn remove(&mut self, hash: &str) -> Option<IndexEntry> {
+        let entry = self.entries.remove(hash)?;
+        self.size -= entry.size;
+        self.dirty = true;
+        Some(entry)
+    }
+}
+
+/// Which entries a listing or purge applies to; unset fields match everything
+#[derive(Debug, Default, Deserialize)]
+pub struct EntryFilter {
+    voice: Option<String>,
+    /// Start of the input text
+    prefix: Option<String>,
+    format: Option<String>,
+    /// Purge every entry; a purge without any filter is rejected otherwise
+    #[serde(default)]
+    all: bool,
+    /// Most entries to list, most recently used first
+    limit: Option<usize>,
+}
+
+impl EntryFilter {
+    fn is_empty(&self) -> bool {
+        self.voice.is_none() && self.prefix.is_none() && self.format.is_none()
+    }
+
+    fn matches(&self, entry: &IndexEntry) -> bool {
+        self.voice.as_ref().is_none_or(|voice| &entry.key.voice == voice)
+            && self.prefix.as_ref().is_none_or(|prefix| entry.key.input.starts_with(prefix.as_str()))
+            && self.format.as_ref().is_none_or(|format| &entry.key.format == format)
+    }
+}
+
+/// Limits applied by pruning, adjustable at runtime
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct CacheLimits {
+    /// Entries not accessed for this long are pruned
+    pub max_age_secs: u64,
+    /// Least recently used entries are pruned while the disk cache is larger than this
+    pub max_bytes: u64,
+    /// Capacity of the in-memory tier
+    pub memory_bytes: u64,
+}
+
+// ============================================================================
+// Audio Cache
+// ============================================================================
+
+pub struct AudioCache {
+    dir: PathBuf,
+    memory: MemoryCache,
+    index: Mutex<Index>,
+    limits: Mutex<CacheLimits>,
+    /// Hashes being written, so identical requests finishing together store an entry once
+    writing: Mutex<HashSet<String>>,
+}
+
+/// Marks a hash as being written until dropped
+struct WriteClaim<'a> {
+    writing: &'a Mutex<HashSet<String>>,
+    hash: &'a str,
+}
+
+impl Drop for WriteClaim<'_> {
+    fn drop(&mut self) {
+        self.writing.lock().unwrap().remove(self.hash);
+    }
+}
+
+impl AudioCache {
+    /// Open `dir`, loading the index and reconciling it with the files present
+    pub fn open(dir: PathBuf, limits: CacheLimits) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+
+        let mut recorded: HashMap<String, IndexEntry> = HashMap::new();
+        let index_path = dir.join(INDEX_FILE);
+        match std::fs::read_to_string(&index_path) {
+            Ok(text) => {
+                for (i, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+                    match serde_json::from_str::<IndexEntry>(line) {
+                        Ok(entry) => {
+                            recorded.insert(entry.file_name(), entry);
+                        }
+                        Err(e) => warn!("Skipping {}:{}: {}", index_path.display(), i + 1, e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", index_path.display())),
+        }
+
+        // The directory is authoritative: files missing from the index are adopted with
+        // what their metadata tells, and index entries without a file are dropped
+        let mut index = Index::default();
+        let recorded_count = recorded.len();
+        for dir_entry in std::fs::read_dir(&dir)? {
+            let dir_entry = dir_entry?;
+            let file_name = dir_entry.file_name().to_string_lossy().to_string();
+            let metadata = dir_entry.metadata()?;
+            if !metadata.is_file() || file_name == INDEX_FILE {
+                continue;
+            }
+            // Leftover from a write interrupted by a crash
+            if file_name.starts_with('.') && file_name.ends_with(".tmp") {
+                let _ = std::fs::remove_file(dir_entry.path());
+                continue;
+            }
+
+            let entry = match recorded.remove(&file_name) {
+                Some(entry) => IndexEntry { size: metadata.len(), ..entry },
+                None => {
+                    let Some((hash, format)) = file_name.rsplit_once('.') else {
+                        continue;
+                    };
+                    let modified = metadata
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map_or_else(unix_now, |d| d.as_secs());
+                    IndexEntry {
+                        hash: hash.to_string(),
+                        key: EntryKey { format: format.to_string(), ..EntryKey::default() },
+                        size: metadata.len(),
+                        hits: 0,
+                        created: modified,
+                        last_access: modified,
+                    }
+                }
+            };
+            index.insert(entry);
+        }
+        index.dirty = !recorded.is_empty() || index.entries.len() != recorded_count;
+        info!("Cache index: {} entries, {} bytes", index.entries.len(), index.size);
+        METRICS.cache_size_bytes.set(index.size as i64);
+
+        Ok(AudioCache {
+            memory: MemoryCache::new(limits.memory_bytes as usize),
+            dir,
+            index: Mutex::new(index),
+            limits: Mutex::new(limits),
+            writing: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn path(&self, hash: &str, format: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", hash, format))
+    }
+
+    /// Look up encoded audio in memory, then on disk. A hit also refreshes `pcm_hash`,
+    /// the PCM it was encoded from, so that is not pruned by age while its encodings are served.
+    pub async fn get(&self, hash: &str, format: &str, pcm_hash: &str) -> Option<Bytes> {
+        let audio = match self.memory.get(hash) {
+            Some(audio) => {
+                self.touch(hash);
+                audio
+            }
+            None => {
+                let audio = self.read(hash, format).await?;
+                self.memory.insert(hash, audio.clone());
+                audio
+            }
+        };
+        self.refresh(pcm_hash);
+        Some(audio)
+    }
+
+    /// Look up canonical PCM; it is only kept on disk, leaving memory to encoded audio
+    pub async fn get_pcm(&self, hash: &str) -> Option<Vec<f32>> {
+        let bytes = self.read(hash, PCM_FORMAT).await?;
+        Some(bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+    }
+
+    /// Store encoded audio in memory and on disk. Memory gets it even when the disk
+    /// write is skipped or fails, so fresh audio is served from memory either way.
+    pub async fn put(&self, hash: &str, key: EntryKey, audio: Bytes) {
+        self.memory.insert(hash, audio.clone());
+        self.write(hash, key, &audio).await;
+    }
+
+    /// Store canonical PCM on disk
+    pub async fn put_pcm(&self, hash: &str, key: EntryKey, samples: &[f32]) {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.write(hash, EntryKey { format: PCM_FORMAT.to_string(), ..key }, &bytes).await;
+    }
+
+    async fn read(&self, hash: &str, format: &str) -> Option<Bytes> {
+        match read_verified(&self.path(hash, format)).await {
+            Some(bytes) => {
+                self.touch(hash);
+                Some(bytes)
+            }
+            None => {
+                self.index.lock().unwrap().remove(hash);
+                None
+            }
+        }
+    }
+
+    /// Write a disk entry and index it; a failure is only logged
+    async fn write(&self, hash: &str, key: EntryKey, audio: &[u8]) {
+        let Some(_claim) = self.claim(hash) else {
+            return;
+        };
+        if let Err(e) = write_verified(&self.path(hash, &key.format), audio).await {
+            error!("Failed to write to cache: {:#}", e);
+            return;
+        }
+        let now = unix_now();
+        self.index.lock().unwrap().insert(IndexEntry {
+            hash: hash.to_string(),
+            key,
+            size: (HEADER_LEN + audio.len()) as u64,
+            hits: 0,
+            created: now,
+            last_access: now,
+        });
+    }
+
+    /// Claim `hash` for writing, unless it is already stored or being written
+    fn claim<'a>(&'a self, hash: &'a str) -> Option<WriteClaim<'a>> {
+        let mut writing = self.writing.lock().unwrap();
+        if self.index.lock().unwrap().entries.contains_key(hash) || !writing.insert(hash.to_string()) {
+            return None;
+        }
+        Some(WriteClaim { writing: &self.writing, hash })
+    }
+
+    /// Record a hit in the index
+    fn touch(&self, hash: &str) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(entry) = index.entries.get_mut(hash) {
+            entry.hits += 1;
+            entry.last_access = unix_now();
+            index.dirty = true;
+        }
+    }
+
+    /// Mark an entry as used without counting a hit
+    fn refresh(&self, hash: &str) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(entry) = index.entries.get_mut(hash) {
+            entry.last_access = unix_now();
+            index.dirty = true;
+        }
+    }
+
+    pub fn limits(&self) -> CacheLimits {
+        *self.limits.lock().unwrap()
+    }
+
+    /// Remove entries from the index, memory and disk
+    async fn remove_entries(&self, entries: &[IndexEntry]) {
+        for entry in entries {
+            self.memory.remove(&entry.hash);
+            let path = self.dir.join(entry.file_name());
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    error!("Failed to remove cache file {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Drop entries idle for longer than the age limit, then the least recently used
+    /// ones until the cache fits the size limit, encoded entries before PCM
+    pub async fn prune(&self) {
+        let limits = self.limits();
+        let cutoff = unix_now().saturating_sub(limits.max_age_secs);
+
+        let victims = {
+            let mut index = self.index.lock().unwrap();
+            let expired: Vec<String> = index
+                .entries
+                .values()
+                .filter(|entry| entry.last_access < cutoff)
+                .map(|entry| entry.hash.clone())
+                .collect();
+            let mut victims: Vec<IndexEntry> = expired.iter().filter_map(|hash| index.remove(hash)).collect();
+
+            let mut by_tier: Vec<(bool, u64, String)> = index
+                .entries
+                .values()
+                .map(|entry| (entry.is_pcm(), entry.last_access, entry.hash.clone()))
+                .collect();
+            by_tier.sort_unstable();
+            for (_, _, hash) in by_tier {
+                if index.size <= limits.max_bytes {
+                    break;
+                }
+                victims.extend(index.remove(&hash));
+            }
+            METRICS.cache_size_bytes.set(index.size as i64);
+            victims
+        };
+
+        if !victims.is_empty() {
+            info!("Pruning {} cache entries", victims.len());
+        }
+        self.remove_entries(&victims).await;
+    }
+
+    /// Write the index back if it changed since the last flush
+    pub async fn flush_index(&self) {
+        let contents = {
+            let mut index = self.index.lock().unwrap();
+            if !index.dirty {
+                return;
+            }
+            index.dirty = false;
+            let mut contents = String::new();
+            for entry in index.entries.values() {
+                contents.push_str(&serde_json::to_string(entry).unwrap());
+                contents.push('\n');
+            }
+            contents
+        };
+        if let Err(e) = write_atomic(&self.dir.join(INDEX_FILE), contents.as_bytes()).await {
+            error!("Failed to write cache index: {:#}", e);
+            self.index.lock().unwrap().dirty = true;
+        }
+    }
+}
+
+/// Prune every `prune_interval` and keep the index file current
+pub async fn maintenance_task(cache: Arc<AudioCache>, prune_interval: Duration) {
+    let mut prune = tokio::time::interval(prune_interval);
+    let mut flush = tokio::time::interval(INDEX_FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = prune.tick() => cache.prune().await,
+            _ = flush.tick() => {}
+        }
+        cache.flush_index().await;
+    }
+}
+
+// ============================================================================
+// In-Memory Tier
+// ============================================================================
+
+struct Entry {
+    audio: Bytes,
+    /// Position in `MemoryInner::recency`
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct MemoryInner {
+    entries: HashMap<String, Entry>,
+    /// Least recently used first
+    recency: BTreeMap<u64, String>,
+    size: usize,
+    capacity: usize,
+    clock: u64,
+}
+
+impl MemoryInner {
+    fn remove(&mut self, hash: &str) {
+        if let Some(old) = self.entries.remove(hash) {
+            self.size -= old.audio.len();
+            self.recency.remove(&old.last_used);
+        }
+    }
+
+    /// Evict the least recently used entries until `extra` more bytes fit
+    fn make_room(&mut self, extra: usize) {
+        while self.size + extra > self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.size -= evicted.audio.len();
+                METRICS.memory_cache_evictions.inc();
+            }
+        }
+    }
+
+    fn update_metrics(&self) {
+        METRICS.memory_cache_size_bytes.set(self.size as i64);
+        METRICS.memory_cache_entries.set(self.entries.len() as i64);
+    }
+}
+
+/// Encoded audio keyed by the cache hash, evicting the least recently used entries
+/// once `capacity` bytes are held. A capacity of 0 disables the tier.
+pub struct MemoryCache {
+    inner: Mutex<MemoryInner>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        MemoryCache {
+            inner: Mutex::new(MemoryInner { capacity, ..MemoryInner::default() }),
+        }
+    }
+
+    pub fn get(&self, hash: &str) -> Option<Bytes> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = inner.entries.get_mut(hash)?;
+        let previous = std::mem::replace(&mut entry.last_used, clock);
+        let audio = entry.audio.clone();
+        inner.recency.remove(&previous);
+        inner.recency.insert(clock, hash.to_string());
+        METRICS.memory_cache_hits.inc();
+        Some(audio)
+    }
+
+    /// Insert or replace an entry; audio larger than the whole capacity is not kept
+    pub fn insert(&self, hash: &str, audio: Bytes) {
+        let mut inner = self.inner.lock().unwrap();
+        if audio.len() > inner.capacity {
+            return;
+        }
+        inner.remove(hash);
+        inner.make_room(audio.len());
+
+        inner.clock += 1;
+        let clock = inner.clock;
+        inner.size += audio.len();
+        inner.recency.insert(clock, hash.to_string());
+        inner.entries.insert(hash.to_string(), Entry { audio, last_used: clock });
+        inner.update_metrics();
+    }
+
+    pub fn remove(&self, hash: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(hash);
+        inner.update_metrics();
+    }
+
+    /// Change the capacity, evicting entries that no longer fit
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity;
+        inner.make_room(0);
+        inner.update_metrics();
+    }
+
+    /// Entries and bytes held
+    fn usage(&self) -> (usize, usize) {
+        let inner = self.inner.lock().unwrap();
+        (inner.entries.len(), inner.size)
+    }
+}
+
+// ============================================================================
+// Sentence Cache
+// ============================================================================
+
+/// Key of one synthesized chunk: its normalized text (with language tags), the voice's
+/// style fingerprint, speed, steps and the chunk's seed
+pub fn sentence_key(normalized: &str, style_fingerprint: &str, speed: f32, total_step: usize, seed: u64) -> String {
+    let key = format!("{}:{:.2}:{}:{}:{}", style_fingerprint, speed, total_step, seed, normalized);
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+struct SentenceEntry {
+    samples: Vec<f32>,
+    duration: f32,
+    /// Position in `SentenceInner::recency`
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct SentenceInner {
+    entries: HashMap<String, SentenceEntry>,
+    /// Least recently used first
+    recency: BTreeMap<u64, String>,
+    size: usize,
+    clock: u64,
+}
+
+/// Synthesized chunks keyed by `sentence_key`, so re-rendering an edited text only
+/// synthesizes the chunks that changed. Least recently used chunks are evicted once
+/// `capacity` bytes of samples are held.
+pub struct SentenceCache {
+    inner: Mutex<SentenceInner>,
+    capacity: usize,
+}
+
+impl SentenceCache {
+    pub fn new(capacity: usize) -> Self {
+        SentenceCache { inner: Mutex::new(SentenceInner::default()), capacity }
+    }
+
+    /// A chunk's samples and duration
+    pub fn get(&self, key: &str) -> Option<(Vec<f32>, f32)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let Some(entry) = inner.entries.get_mut(key) else {
+            METRICS.sentence_cache_misses.inc();
+            return None;
+        };
+        let previous = std::mem::replace(&mut entry.last_used, clock);
+        let output = (entry.samples.clone(), entry.duration);
+        inner.recency.remove(&previous);
+        inner.recency.insert(clock, key.to_string());
+        METRICS.sentence_cache_hits.inc();
+        Some(output)
+    }
+
+    pub fn insert(&self, key: &str, samples: &[f32], duration: f32) {
+        let bytes = std::mem::size_of_val(samples);
+        if bytes > self.capacity {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(old) = inner.entries.remove(key) {
+            inner.size -= std::mem::size_of_val(old.samples.as_slice());
+            inner.recency.remove(&old.last_used);
+        }
+        while inner.size + bytes > self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = inner.entries.remove(&oldest) {
+                inner.size -= std::mem::size_of_val(evicted.samples.as_slice());
+            }
+        }
+
+        inner.clock += 1;
+        let clock = inner.clock;
+        inner.size += bytes;
+        inner.recency.insert(clock, key.to_string());
+        inner.entries.insert(key.to_string(), SentenceEntry { samples: samples.to_vec(), duration, last_used: clock });
+        METRICS.sentence_cache_size_bytes.set(inner.size as i64);
+    }
+}
+
+// ============================================================================
+// Admin Endpoints
+// ============================================================================
+
+pub async fn cache_stats(State(state): State<Arc<AppState>>) -> Response {
+    let cache = &state.cache;
+    let (memory_entries, memory_bytes) = cache.memory.usage();
+    let index = cache.index.lock().unwrap();
+
+    let mut by_format: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for entry in index.entries.values() {
+        let totals = by_format.entry(entry.key.format.as_str()).or_default();
+        totals.0 += 1;
+        totals.1 += entry.size;
+    }
+    let by_format: serde_json::Map<String, serde_json::Value> = by_format
+        .into_iter()
+        .map(|(format, (entries, bytes))| (format.to_string(), serde_json::json!({ "entries": entries, "size_bytes": bytes })))
+        .collect();
+
+    Json(serde_json::json!({
+        "entries": index.entries.len(),
+        "size_bytes": index.size,
+        "hits": index.entries.values().map(|entry| entry.hits).sum::<u64>(),
+        "by_format": by_format,
+        "memory": { "entries": memory_entries, "size_bytes": memory_bytes },
+        "limits": cache.limits(),
+    }))
+    .into_response()
+}
+
+/// Entries matching the filter, most recently used first
+pub async fn list_entries(State(state): State<Arc<AppState>>, Query(filter): Query<EntryFilter>) -> Response {
+    let index = state.cache.index.lock().unwrap();
+    let mut entries: Vec<&IndexEntry> = index.entries.values().filter(|entry| filter.matches(entry)).collect();
+    let total = entries.len();
+    entries.sort_unstable_by_key(|entry| std::cmp::Reverse(entry.last_access));
+    entries.truncate(filter.limit.unwrap_or(DEFAULT_LIST_LIMIT));
+
+    Json(serde_json::json!({
+        "object": "list",
+        "total": total,
+        "data": entries,
+    }))
+    .into_response()
+}
+
+pub async fn purge_entries(State(state): State<Arc<AppState>>, Query(filter): Query<EntryFilter>) -> Response {
+    if filter.is_empty() && !filter.all {
+        return openai_error(
+            StatusCode::BAD_REQUEST,
+            "Give at least one of voice, prefix or format, or all=true to purge the whole cache",
+            "invalid_request_error",
+            None,
+            None,
+        );
+    }
+
+    let cache = &state.cache;
+    let purged = {
+        let mut index = cache.index.lock().unwrap();
+        let hashes: Vec<String> = index
+            .entries
+            .values()
+            .filter(|entry| filter.matches(entry))
+            .map(|entry| entry.hash.clone())
+            .collect();
+        let purged: Vec<IndexEntry> = hashes.iter().filter_map(|hash| index.remove(hash)).collect();
+        METRICS.cache_size_bytes.set(index.size as i64);
+        purged
+    };
+    cache.remove_entries(&purged).await;
+    cache.flush_index().await;
+
+    let freed: u64 = purged.iter().map(|entry| entry.size).sum();
+    info!("Purged {} cache entries ({} bytes)", purged.len(), freed);
+    Json(serde_json::json!({ "deleted": purged.len(), "freed_bytes": freed })).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsUpdate {
+    max_age_secs: Option<u64>,
+    max_bytes: Option<u64>,
+    memory_bytes: Option<u64>,
+}
+
+/// Change limits until the next restart; the disk cache is pruned to them right away
+pub async fn update_limits(State(state): State<Arc<AppState>>, Json(update): Json<LimitsUpdate>) -> Response {
+    let cache = &state.cache;
+    let limits = {
+        let mut limits = cache.limits.lock().unwrap();
+        if let Some(max_age_secs) = update.max_age_secs {
+            limits.max_age_secs = max_age_secs;
+        }
+        if let Some(max_bytes) = update.max_bytes {
+            limits.max_bytes = max_bytes;
+        }
+        if let Some(memory_bytes) = update.memory_bytes {
+            limits.memory_bytes = memory_bytes;
+        }
+        *limits
+    };
+    info!("Cache limits changed to {:?}", limits);
+
+    cache.memory.set_capacity(limits.memory_bytes as usize);
+    cache.prune().await;
+    Json(limits).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_LIMITS: CacheLimits = CacheLimits { max_age_secs: u64::MAX, max_bytes: u64::MAX, memory_bytes: 1 << 20 };
+
+    fn key(format: &str) -> EntryKey {
+        EntryKey { input: "Hello there.".to_string(), voice: "Sarah".to_string(), format: format.to_string(), ..EntryKey::default() }
+    }
+
+    fn entry(hash: &str, format: &str, size: u64) -> IndexEntry {
+        IndexEntry { hash: hash.to_string(), key: key(format), size, hits: 0, created: 0, last_access: 0 }
+    }
+
+    fn audio(len: usize) -> Bytes {
+        Bytes::from(vec![7u8; len])
+    }
+
+    fn set_last_access(cache: &AudioCache, hash: &str, last_access: u64) {
+        cache.index.lock().unwrap().entries.get_mut(hash).unwrap().last_access = last_access;
+    }
+
+    #[test]
+    fn memory_cache_evicts_least_recently_used() {
+        let memory = MemoryCache::new(10);
+        memory.insert("a", audio(4));
+        memory.insert("b", audio(4));
+        assert!(memory.get("a").is_some());
+
+        memory.insert("c", audio(4));
+        assert!(memory.get("b").is_none());
+        assert!(memory.get("a").is_some());
+        assert!(memory.get("c").is_some());
+        assert_eq!(memory.usage(), (2, 8));
+    }
+
+    #[test]
+    fn memory_cache_accounts_for_replace_and_remove() {
+        let memory = MemoryCache::new(10);
+        memory.insert("a", audio(4));
+        memory.insert("a", audio(6));
+        assert_eq!(memory.usage(), (1, 6));
+        assert_eq!(memory.get("a").unwrap().len(), 6);
+
+        memory.remove("a");
+        memory.remove("a");
+        assert_eq!(memory.usage(), (0, 0));
+    }
+
+    #[test]
+    fn memory_cache_skips_oversized_audio_and_shrinks_to_capacity() {
+        let memory = MemoryCache::new(10);
+        memory.insert("big", audio(11));
+        assert!(memory.get("big").is_none());
+
+        memory.insert("a", audio(4));
+        memory.insert("b", audio(4));
+        memory.set_capacity(5);
+        assert_eq!(memory.usage(), (1, 4));
+        assert!(memory.get("b").is_some());
+
+        memory.set_capacity(0);
+        assert_eq!(memory.usage(), (0, 0));
+    }
+
+    #[test]
+    fn index_tracks_size_across_replace_and_remove() {
+        let mut index = Index::default();
+        index.insert(entry("a", "mp3", 100));
+        index.insert(entry("b", "mp3", 50));
+        index.insert(entry("a", "mp3", 30));
+        assert_eq!(index.size, 80);
+        assert_eq!(index.entries.len(), 2);
+
+        index.dirty = false;
+        assert_eq!(index.remove("a").unwrap().size, 30);
+        assert!(index.remove("a").is_none());
+        assert_eq!(index.size, 50);
+        assert!(index.dirty);
+    }
+
+    #[test]
+    fn entry_filter_matches_every_given_field() {
+        let filter = EntryFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.matches(&entry("a", "mp3", 1)));
+
+        let filter = EntryFilter { voice: Some("Sarah".to_string()), prefix: Some("Hello".to_string()), ..EntryFilter::default() };
+        assert!(!filter.is_empty());
+        assert!(filter.matches(&entry("a", "mp3", 1)));
+
+        let filter = EntryFilter { prefix: Some("there".to_string()), ..EntryFilter::default() };
+        assert!(!filter.matches(&entry("a", "mp3", 1)));
+        let filter = EntryFilter { voice: Some("Sarah".to_string()), format: Some("wav".to_string()), ..EntryFilter::default() };
+        assert!(!filter.matches(&entry("a", "mp3", 1)));
+
+        // `all` alone does not narrow the match
+        let filter = EntryFilter { all: true, ..EntryFilter::default() };
+        assert!(filter.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stored_audio_round_trips_and_hits_are_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = AudioCache::open(dir.path().to_path_buf(), NO_LIMITS).unwrap();
+        cache.put("a", key("mp3"), audio(16)).await;
+        cache.put_pcm("p", key("mp3"), &[0.5, -0.25]).await;
+        cache.flush_index().await;
+
+        assert_eq!(cache.get("a", "mp3", "p").await.unwrap(), audio(16));
+        assert_eq!(cache.get_pcm("p").await.unwrap(), vec![0.5, -0.25]);
+        assert!(cache.index.lock().unwrap().dirty);
+        cache.flush_index().await;
+        drop(cache);
+
+        let reopened = AudioCache::open(dir.path().to_path_buf(), NO_LIMITS).unwrap();
+        let index = reopened.index.lock().unwrap();
+        assert_eq!(index.entries["a"].hits, 1);
+        assert_eq!(index.entries["p"].hits, 1);
+        assert_eq!(index.entries["p"].key.format, PCM_FORMAT);
+        assert_eq!(index.size, (HEADER_LEN * 2 + 16 + 8) as u64);
+        assert!(!index.dirty);
+    }
+
+    #[tokio::test]
+    async fn open_reconciles_the_index_with_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorded = [entry("kept", "mp3", 0), entry("missing", "wav", 0)];
+        let index: String = recorded.iter().map(|entry| serde_json::to_string(entry).unwrap() + "\n").collect();
+        std::fs::write(dir.path().join(INDEX_FILE), index + "not json\n").unwrap();
+        write_verified(&dir.path().join("kept.mp3"), &[1, 2, 3]).await.unwrap();
+        write_verified(&dir.path().join("adopted.opus"), &[4, 5]).await.unwrap();
+        std::fs::write(dir.path().join(".kept.mp3.0123456789abcdef.tmp"), b"partial").unwrap();
+
+        let cache = AudioCache::open(dir.path().to_path_buf(), NO_LIMITS).unwrap();
+        let index = cache.index.lock().unwrap();
+        let mut hashes: Vec<&str> = index.entries.keys().map(String::as_str).collect();
+        hashes.sort_unstable();
+        assert_eq!(hashes, ["adopted", "kept"]);
+
+        // Recorded fields are kept, with the size taken from the file
+        assert_eq!(index.entries["kept"].key.voice, "Sarah");
+        assert_eq!(index.entries["kept"].size, (HEADER_LEN + 3) as u64);
+        assert_eq!(index.entries["adopted"].key.format, "opus");
+        assert_eq!(index.size, (HEADER_LEN * 2 + 5) as u64);
+        assert!(index.dirty);
+        assert!(!dir.path().join(".kept.mp3.0123456789abcdef.tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn corrupt_files_are_removed_on_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = AudioCache::open(dir.path().to_path_buf(), CacheLimits { memory_bytes: 0, ..NO_LIMITS }).unwrap();
+        cache.put("a", key("mp3"), audio(16)).await;
+
+        let path = dir.path().join("a.mp3");
+        let mut contents = std::fs::read(&path).unwrap();
+        *contents.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, contents).unwrap();
+
+        assert!(cache.get("a", "mp3", "p").await.is_none());
+        assert!(!path.exists());
+        assert!(cache.index.lock().unwrap().entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn encoded_hits_keep_their_pcm_from_expiring() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = AudioCache::open(dir.path().to_path_buf(), NO_LIMITS).unwrap();
+        cache.put_pcm("pcm", key("mp3"), &[0.0; 4]).await;
+        cache.put("enc", key("mp3"), audio(16)).await;
+        set_last_access(&cache, "pcm", unix_now() - 1000);
+
+        assert!(cache.get("enc", "mp3", "pcm").await.is_some());
+        *cache.limits.lock().unwrap() = CacheLimits { max_age_secs: 500, ..NO_LIMITS };
+        cache.prune().await;
+        let index = cache.index.lock().unwrap();
+        assert!(index.entries.contains_key("pcm"));
+        assert_eq!(index.entries["pcm"].hits, 0);
+    }
+
+    #[tokio::test]
+    async fn prune_drops_expired_entries_then_encoded_before_pcm() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = AudioCache::open(dir.path().to_path_buf(), NO_LIMITS).unwrap();
+        cache.put_pcm("pcm-old", key("mp3"), &[0.0; 4]).await;
+        cache.put_pcm("pcm-new", key("mp3"), &[0.0; 4]).await;
+        cache.put("enc-old", key("mp3"), audio(16)).await;
+        cache.put("enc-new", key("mp3"), audio(16)).await;
+        cache.put("expired", key("wav"), audio(16)).await;
+        let now = unix_now();
+        set_last_access(&cache, "pcm-old", now - 40);
+        set_last_access(&cache, "pcm-new", now - 30);
+        set_last_access(&cache, "enc-old", now - 20);
+        set_last_access(&cache, "enc-new", now - 10);
+        set_last_access(&cache, "expired", now - 1000);
+
+        // Room for three entries: the expired one goes first, then the older encoded entry
+        let entry_size = (HEADER_LEN + 16) as u64;
+        *cache.limits.lock().unwrap() = CacheLimits { max_age_secs: 500, max_bytes: entry_size * 3, ..NO_LIMITS };
+        cache.prune().await;
+        {
+            let index = cache.index.lock().unwrap();
+            let mut hashes: Vec<&str> = index.entries.keys().map(String::as_str).collect();
+            hashes.sort_unstable();
+            assert_eq!(hashes, ["enc-new", "pcm-new", "pcm-old"]);
+            assert_eq!(index.size, entry_size * 3);
+        }
+        assert!(!dir.path().join("expired.wav").exists());
+        assert!(!dir.path().join("enc-old.mp3").exists());
+        assert!(cache.get("enc-old", "mp3", "pcm-old").await.is_none());
+
+        // PCM is only pruned once no encoded entry is left
+        *cache.limits.lock().unwrap() = CacheLimits { max_age_secs: 500, max_bytes: entry_size, ..NO_LIMITS };
+        cache.prune().await;
+        let index = cache.index.lock().unwrap();
+        assert_eq!(index.entries.keys().collect::<Vec<_>>(), ["pcm-new"]);
+    }
+}
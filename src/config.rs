@@ -0,0 +1,368 @@
+// ============================================================================
+// Server Configuration - Command line and environment
+// ============================================================================
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::audio::{self, SUPPORTED_FORMATS};
+use crate::helper::AVAILABLE_LANGS;
+use crate::auth::ApiKeys;
+use crate::queue::Priority;
+use crate::voices::VoiceRegistry;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Supertonic OpenAI-compatible TTS server.
+///
+/// Every option can also be set through the environment variable shown in `--help`.
+#[derive(Parser, Debug, Clone)]
+#[command(version, about)]
+pub struct Args {
+    /// Address to listen on
+    #[arg(long, env = "SUPERTONIC_HOST", default_value = "0.0.0.0")]
+    pub host: IpAddr,
+
+    /// Port to listen on
+    #[arg(long, env = "SUPERTONIC_PORT", default_value_t = 8080)]
+    pub port: u16,
+
+    /// Directory containing the ONNX models, tts.json and unicode_indexer.json
+    #[arg(long, env = "SUPERTONIC_ONNX_DIR", default_value = "assets/onnx")]
+    pub onnx_dir: PathBuf,
+
+    /// Number of TTS engines; each holds its own copy of the models
+    #[arg(long, env = "SUPERTONIC_ENGINES", default_value_t = 1,
+          value_parser = clap::value_parser!(u64).range(1..=64))]
+    pub engines: u64,
+
+    /// Chunks of one input synthesized together by a single engine; 1 disables chunk batching
+    #[arg(long, env = "SUPERTONIC_CHUNK_BATCH_SIZE", default_value_t = 1,
+          value_parser = clap::value_parser!(u64).range(1..=64))]
+    pub chunk_batch_size: u64,
+
+    /// Milliseconds to wait for more chunks before running a batch; 0 disables batching
+    #[arg(long, env = "SUPERTONIC_BATCH_WINDOW_MS", default_value_t = 0)]
+    pub batch_window_ms: u64,
+
+    /// Most chunks synthesized together in one batch
+    #[arg(long, env = "SUPERTONIC_MAX_BATCH_SIZE", default_value_t = 8,
+          value_parser = clap::value_parser!(u64).range(1..=64))]
+    pub max_batch_size: u64,
+
+    /// Speech requests admitted for synthesis at once; more get 503 until some finish
+    #[arg(long, env = "SUPERTONIC_QUEUE_MAX_DEPTH", default_value_t = 64,
+          value_parser = clap::value_parser!(u64).range(1..))]
+    pub queue_max_depth: u64,
+
+    /// Seconds a speech request may take before its synthesis is cancelled; 0 disables the limit
+    #[arg(long, env = "SUPERTONIC_REQUEST_TIMEOUT_SECS", default_value_t = 300)]
+    pub request_timeout_secs: u64,
+
+    /// Directory containing the voice style JSON files
+    #[arg(long, env = "SUPERTONIC_VOICE_STYLE_DIR", default_value = "assets/voice_styles")]
+    pub voice_style_dir: PathBuf,
+
+    /// Directory for cached audio
+    #[arg(long, env = "SUPERTONIC_CACHE_DIR", default_value = "cache")]
+    pub cache_dir: PathBuf,
+
+    /// Cached files older than this many seconds are pruned
+    #[arg(long, env = "SUPERTONIC_CACHE_MAX_AGE_SECS", default_value_t = 86400 * 3)]
+    pub cache_max_age_secs: u64,
+
+    /// The oldest cached files are pruned while the cache is larger than this many bytes
+    #[arg(long, env = "SUPERTONIC_CACHE_MAX_BYTES", default_value_t = 1024 * 1024 * 1024)]
+    pub cache_max_bytes: u64,
+
+    /// Bytes of recently used audio kept in memory in front of the disk cache (0 disables it)
+    #[arg(long, env = "SUPERTONIC_MEMORY_CACHE_BYTES", default_value_t = 64 * 1024 * 1024)]
+    pub memory_cache_bytes: u64,
+
+    /// Bytes of synthesized chunks kept in memory for reuse when a text is re-rendered with edits (0 disables it)
+    #[arg(long, env = "SUPERTONIC_SENTENCE_CACHE_BYTES", default_value_t = 128 * 1024 * 1024)]
+    pub sentence_cache_bytes: u64,
+
+    /// Seconds between cache pruning runs
+    #[arg(long, env = "SUPERTONIC_CACHE_PRUNE_INTERVAL_SECS", default_value_t = 3600,
+          value_parser = clap::value_parser!(u64).range(1..))]
+    pub cache_prune_interval_secs: u64,
+
+    /// ffmpeg executable for formats without a native encoder; empty disables ffmpeg
+    #[arg(long, env = "SUPERTONIC_FFMPEG_PATH", default_value = "ffmpeg")]
+    pub ffmpeg_path: String,
+
+    /// Voice used when a request does not name one
+    #[arg(long, env = "SUPERTONIC_DEFAULT_VOICE")]
+    pub default_voice: Option<String>,
+
+    /// total_step used when a request does not set one (1-10)
+    #[arg(long, env = "SUPERTONIC_DEFAULT_TOTAL_STEP", default_value_t = 5,
+          value_parser = clap::value_parser!(u64).range(1..=10))]
+    pub default_total_step: u64,
+
+    /// File of API keys, one `<key> [scopes] [name]` entry per line
+    #[arg(long, env = "SUPERTONIC_API_KEYS_FILE")]
+    pub api_keys_file: Option<PathBuf>,
+
+    /// API key entry in the key file format; repeatable, or `;`-separated in the environment
+    #[arg(long = "api-key", env = "SUPERTONIC_API_KEYS", value_delimiter = ';', hide_env_values = true)]
+    pub api_keys: Vec<String>,
+
+    /// Shorthand for an API key with the admin scope
+    #[arg(long, env = "SUPERTONIC_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Requests per minute allowed per API key, or per client IP without one
+    #[arg(long, env = "SUPERTONIC_RATE_LIMIT_RPM",
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub rate_limit_rpm: Option<u32>,
+
+    /// Input characters per minute allowed per API key, or per client IP without one
+    #[arg(long, env = "SUPERTONIC_RATE_LIMIT_CHARS_PER_MIN",
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub rate_limit_chars_per_min: Option<u32>,
+
+    /// TOML or JSON file declaring voice aliases, per-voice defaults and request limits
+    #[arg(long, env = "SUPERTONIC_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Log output format; the level is controlled by RUST_LOG
+    #[arg(long, env = "SUPERTONIC_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+impl Args {
+    pub fn init_logging(&self) {
+        let builder = tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env());
+        match self.log_format {
+            LogFormat::Text => builder.init(),
+            LogFormat::Json => builder.json().init(),
+        }
+    }
+}
+
+// ============================================================================
+// Config File - Aliases, per-voice defaults, request limits and key priorities
+// ============================================================================
+
+/// Settings loaded from `--config`; every field is optional
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Alias name -> style ID; replaces the built-in OpenAI voice names when present
+    aliases: Option<BTreeMap<String, String>>,
+    /// Defaults keyed by voice name or style ID
+    #[serde(default)]
+    voices: HashMap<String, VoiceDefaults>,
+    /// Languages the server accepts; every supported language when unset
+    allowed_languages: Option<Vec<String>>,
+    /// `response_format` used when a request does not set one
+    default_format: Option<String>,
+    #[serde(default)]
+    pub limits: RequestLimits,
+    /// API key name -> priority class; other keys are interactive
+    #[serde(default)]
+    priorities: HashMap<String, Priority>,
+}
+
+/// Defaults applied when a request for this voice leaves a parameter unset
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct VoiceDefaults {
+    pub speed: Option<f32>,
+    pub total_step: Option<usize>,
+    pub lang: Option<String>,
+    /// Languages this voice accepts; must be a subset of `allowed_languages`
+    languages: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestLimits {
+    /// Longest accepted `input`, in characters
+    pub max_input_chars: Option<usize>,
+    pub max_total_step: usize,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    /// Longest deadline a request may ask for with `X-Request-Timeout`, in seconds
+    pub max_request_timeout_secs: u64,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_input_chars: None,
+            max_total_step: 10,
+            min_speed: 0.25,
+            max_speed: 4.0,
+            max_request_timeout_secs: 3600,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Parse a config file; `.json` files are read as JSON, anything else as TOML
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&text)?
+        } else {
+            toml::from_str(&text)?
+        };
+        Ok(config)
+    }
+
+    /// Aliases declared in the file, or `None` to keep the built-in mapping
+    pub fn aliases(&self) -> Option<&BTreeMap<String, String>> {
+        self.aliases.as_ref()
+    }
+
+    /// Priority class of the API key named `key`
+    pub fn priority_for(&self, key: Option<&str>) -> Priority {
+        key.and_then(|name| self.priorities.get(name)).copied().unwrap_or_default()
+    }
+
+    pub fn default_format(&self) -> &str {
+        self.default_format.as_deref().unwrap_or("mp3")
+    }
+
+    /// Make sure the default format can be produced once ffmpeg has been probed.
+    /// A `default_format` set in the file must be; without one, `mp3` falls back to
+    /// `flac` or `wav` when neither its encoder nor ffmpeg is available.
+    pub fn settle_default_format(&mut self) -> Result<()> {
+        if let Some(ref format) = self.default_format {
+            return audio::check_format(format).map_err(|message| anyhow!("Default format: {}", message));
+        }
+        if audio::check_format("mp3").is_err() {
+            let fallback = if audio::check_format("flac").is_ok() { "flac" } else { "wav" };
+            warn!("mp3 needs ffmpeg or the mp3 feature; requests without response_format get {}", fallback);
+            self.default_format = Some(fallback.to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether the file has a `[voices]` entry keyed by `name`
+    pub fn has_voice_defaults(&self, name: &str) -> bool {
+        self.voices.contains_key(name)
+    }
+
+    /// Defaults for `name`, falling back to the entry for its style ID
+    pub fn voice_defaults(&self, name: &str, style_id: &str) -> Option<&VoiceDefaults> {
+        self.voices.get(name).or_else(|| self.voices.get(style_id))
+    }
+
+    /// Languages accepted for `name`, or for any voice when `name` is `None` (e.g. a blend)
+    pub fn languages_for(&self, name: Option<(&str, &str)>) -> Vec<&'static str> {
+        let voice_langs = name
+            .and_then(|(name, style_id)| self.voice_defaults(name, style_id))
+            .and_then(|defaults| defaults.languages.as_ref());
+        match voice_langs.or(self.allowed_languages.as_ref()) {
+            Some(langs) => AVAILABLE_LANGS
+                .iter()
+                .copied()
+                .filter(|lang| langs.iter().any(|l| l == lang))
+                .collect(),
+            None => AVAILABLE_LANGS.to_vec(),
+        }
+    }
+
+    /// Language used when a request leaves it unset: the voice's default, else `en` if
+    /// accepted, else the first accepted language
+    pub fn default_lang_for(&self, name: Option<(&str, &str)>) -> String {
+        let configured = name
+            .and_then(|(name, style_id)| self.voice_defaults(name, style_id))
+            .and_then(|defaults| defaults.lang.clone());
+        configured.unwrap_or_else(|| match self.languages_for(name) {
+            langs if langs.contains(&"en") => "en".to_string(),
+            langs => langs[0].to_string(),
+        })
+    }
+
+    /// Check the file against the loaded voices, after aliases have been applied, and the API keys
+    pub fn validate(&self, registry: &VoiceRegistry, api_keys: &ApiKeys) -> Result<()> {
+        let limits = &self.limits;
+        if !(1..=10).contains(&limits.max_total_step) {
+            bail!("limits.max_total_step must be between 1 and 10");
+        }
+        if !(limits.min_speed > 0.0 && limits.min_speed <= limits.max_speed) {
+            bail!("limits.min_speed must be positive and not above limits.max_speed");
+        }
+        if limits.max_input_chars == Some(0) {
+            bail!("limits.max_input_chars must be positive");
+        }
+        if limits.max_request_timeout_secs == 0 {
+            bail!("limits.max_request_timeout_secs must be positive");
+        }
+
+        if let Some(ref format) = self.default_format {
+            if !SUPPORTED_FORMATS.contains(&format.as_str()) {
+                bail!("default_format '{}' is not one of {:?}", format, SUPPORTED_FORMATS);
+            }
+        }
+
+        if let Some(ref langs) = self.allowed_languages {
+            if langs.is_empty() {
+                bail!("allowed_languages must not be empty");
+            }
+            for lang in langs {
+                if !AVAILABLE_LANGS.contains(&lang.as_str()) {
+                    bail!("allowed_languages: unsupported language '{}'", lang);
+                }
+            }
+        }
+        let allowed = self.languages_for(None);
+
+        for (name, defaults) in &self.voices {
+            if !registry.contains(name) {
+                bail!("voices.{}: not a loaded voice or alias", name);
+            }
+            if let Some(speed) = defaults.speed {
+                if !(limits.min_speed..=limits.max_speed).contains(&speed) {
+                    bail!("voices.{}: speed must be between {} and {}", name, limits.min_speed, limits.max_speed);
+                }
+            }
+            if let Some(total_step) = defaults.total_step {
+                if !(1..=limits.max_total_step).contains(&total_step) {
+                    bail!("voices.{}: total_step must be between 1 and {}", name, limits.max_total_step);
+                }
+            }
+            if let Some(ref langs) = defaults.languages {
+                if langs.is_empty() {
+                    bail!("voices.{}: languages must not be empty", name);
+                }
+                for lang in langs {
+                    if !allowed.contains(&lang.as_str()) {
+                        bail!("voices.{}: language '{}' is not allowed", name, lang);
+                    }
+                }
+            }
+            if let Some(ref lang) = defaults.lang {
+                let langs = self.languages_for(Some((name, registry.style_id_of(name))));
+                if !langs.contains(&lang.as_str()) {
+                    bail!("voices.{}: default lang '{}' is not allowed for this voice", name, lang);
+                }
+            }
+        }
+
+        // A misspelled key name would silently leave that key interactive
+        for name in self.priorities.keys() {
+            if !api_keys.has_name(name) {
+                bail!("priorities.{}: no API key has this name", name);
+            }
+        }
+
+        Ok(())
+    }
+}
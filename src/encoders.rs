@@ -0,0 +1,281 @@
+// ============================================================================
+// Native Encoders - In-process FLAC, Ogg/Opus and MP3 behind cargo features
+// ============================================================================
+//
+// Each encoder is fed PCM chunk by chunk and returns whatever output is complete,
+// so the same code serves whole-buffer conversion and streaming responses.
+// Formats without a compiled-in encoder fall back to ffmpeg.
+
+#![cfg_attr(not(any(feature = "flac", feature = "opus", feature = "mp3")), allow(unused_variables))]
+
+use anyhow::Result;
+
+pub trait NativeEncoder: Send {
+    /// Encode a chunk of samples, returning the output completed so far
+    fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>>;
+    /// Flush the remaining output and end the stream
+    fn finish(self: Box<Self>) -> Result<Vec<u8>>;
+}
+
+/// Formats with an in-process encoder in this build
+pub const NATIVE_FORMATS: &[&str] = &[
+    #[cfg(feature = "flac")]
+    "flac",
+    #[cfg(feature = "opus")]
+    "opus",
+    #[cfg(feature = "mp3")]
+    "mp3",
+];
+
+/// An in-process encoder for `format`, or `None` when this build has none
+pub fn native_encoder(format: &str, sample_rate: i32) -> Option<Result<Box<dyn NativeEncoder>>> {
+    match format {
+        #[cfg(feature = "flac")]
+        "flac" => Some(Ok(Box::new(flac::FlacEncoder::new(sample_rate)))),
+        #[cfg(feature = "opus")]
+        "opus" => Some(opus::OpusEncoder::new(sample_rate).map(|e| Box::new(e) as Box<dyn NativeEncoder>)),
+        #[cfg(feature = "mp3")]
+        "mp3" => Some(mp3::Mp3Encoder::new(sample_rate).map(|e| Box::new(e) as Box<dyn NativeEncoder>)),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "flac")]
+mod flac {
+    use anyhow::{anyhow, Result};
+    use flacenc::component::BitRepr;
+    use flacenc::error::Verify;
+
+    use super::NativeEncoder;
+    use crate::audio::sample_to_i16;
+
+    /// FLAC's STREAMINFO header needs the whole signal, so samples are buffered
+    /// and the file is produced in one piece by `finish`
+    pub struct FlacEncoder {
+        sample_rate: i32,
+        samples: Vec<i32>,
+    }
+
+    impl FlacEncoder {
+        pub fn new(sample_rate: i32) -> Self {
+            FlacEncoder { sample_rate, samples: Vec::new() }
+        }
+    }
+
+    impl NativeEncoder for FlacEncoder {
+        fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>> {
+            self.samples.extend(samples.iter().map(|&s| sample_to_i16(s) as i32));
+            Ok(Vec::new())
+        }
+
+        fn finish(self: Box<Self>) -> Result<Vec<u8>> {
+            let config = flacenc::config::Encoder::default()
+                .into_verified()
+                .map_err(|(_, e)| anyhow!("Invalid FLAC encoder config: {:?}", e))?;
+            let source = flacenc::source::MemSource::from_samples(&self.samples, 1, 16, self.sample_rate as usize);
+            let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+                .map_err(|e| anyhow!("FLAC encoding failed: {:?}", e))?;
+
+            let mut sink = flacenc::bitsink::ByteSink::new();
+            stream
+                .write(&mut sink)
+                .map_err(|e| anyhow!("FLAC encoding failed: {:?}", e))?;
+            Ok(sink.as_slice().to_vec())
+        }
+    }
+}
+
+#[cfg(feature = "opus")]
+mod opus {
+    use anyhow::{anyhow, Result};
+    use audiopus::coder::Encoder;
+    use audiopus::{Application, Bitrate, Channels, SampleRate};
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    use super::NativeEncoder;
+
+    /// Opus always runs at 48 kHz internally; granule positions count 48 kHz samples
+    const OPUS_RATE: u32 = 48_000;
+    /// 20 ms frames
+    const FRAME_SAMPLES: usize = 960;
+    /// Recommended upper bound for one encoded packet
+    const MAX_PACKET_BYTES: usize = 4000;
+    const BITRATE: i32 = 48_000;
+    const SERIAL: u32 = 0x5354_5453;
+
+    /// Linear interpolation to 48 kHz, carrying its position across chunks
+    struct Resampler {
+        /// Input samples per output sample
+        step: f64,
+        /// Position of the next output sample, relative to the start of the next chunk
+        pos: f64,
+        /// Last sample of the previous chunk, at position -1
+        prev: f32,
+    }
+
+    impl Resampler {
+        fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+            let len = input.len() as f64;
+            let sample = |i: isize| if i < 0 { self.prev } else { input[i as usize] };
+            let mut pos = self.pos;
+            // Interpolation at `pos` needs the sample after it, which may be in the next chunk
+            while pos + 1.0 < len {
+                let base = pos.floor();
+                let frac = (pos - base) as f32;
+                let i = base as isize;
+                out.push(sample(i) + (sample(i + 1) - sample(i)) * frac);
+                pos += self.step;
+            }
+            if let Some(&last) = input.last() {
+                self.prev = last;
+                self.pos = pos - len;
+            }
+        }
+    }
+
+    pub struct OpusEncoder {
+        encoder: Encoder,
+        writer: PacketWriter<Vec<u8>>,
+        resampler: Resampler,
+        /// Resampled audio not yet making up a whole frame
+        pending: Vec<f32>,
+        pre_skip: u64,
+        /// Samples encoded so far, at 48 kHz
+        encoded: u64,
+        /// Real (not padding) samples received so far, at 48 kHz
+        received: u64,
+    }
+
+    impl OpusEncoder {
+        pub fn new(sample_rate: i32) -> Result<Self> {
+            let mut encoder = Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Audio)
+                .map_err(|e| anyhow!("Failed to create Opus encoder: {}", e))?;
+            encoder
+                .set_bitrate(Bitrate::BitsPerSecond(BITRATE))
+                .map_err(|e| anyhow!("Failed to set Opus bitrate: {}", e))?;
+            let pre_skip = encoder.lookahead().map_err(|e| anyhow!("Failed to query Opus lookahead: {}", e))?;
+
+            // Identification and comment headers, each on its own page (RFC 7845)
+            let mut head = Vec::with_capacity(19);
+            head.extend_from_slice(b"OpusHead");
+            head.push(1); // version
+            head.push(1); // channels
+            head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+            head.extend_from_slice(&(sample_rate as u32).to_le_bytes());
+            head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+            head.push(0); // mapping family
+
+            let vendor = concat!("supertonic-tts ", env!("CARGO_PKG_VERSION"));
+            let mut tags = Vec::new();
+            tags.extend_from_slice(b"OpusTags");
+            tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+            tags.extend_from_slice(vendor.as_bytes());
+            tags.extend_from_slice(&0u32.to_le_bytes()); // user comments
+
+            let mut writer = PacketWriter::new(Vec::new());
+            writer.write_packet(head.into_boxed_slice(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+            writer.write_packet(tags.into_boxed_slice(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+            Ok(OpusEncoder {
+                encoder,
+                writer,
+                resampler: Resampler { step: sample_rate as f64 / OPUS_RATE as f64, pos: 0.0, prev: 0.0 },
+                pending: Vec::new(),
+                pre_skip: pre_skip as u64,
+                encoded: 0,
+                received: 0,
+            })
+        }
+
+        fn write_frame(&mut self, frame: &[f32], end: PacketWriteEndInfo, granule: u64) -> Result<()> {
+            let mut packet = vec![0u8; MAX_PACKET_BYTES];
+            let len = self
+                .encoder
+                .encode_float(frame, &mut packet)
+                .map_err(|e| anyhow!("Opus encoding failed: {}", e))?;
+            packet.truncate(len);
+            self.writer.write_packet(packet.into_boxed_slice(), SERIAL, end, granule)?;
+            Ok(())
+        }
+    }
+
+    impl NativeEncoder for OpusEncoder {
+        fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>> {
+            let before = self.pending.len();
+            self.resampler.process(samples, &mut self.pending);
+            self.received += (self.pending.len() - before) as u64;
+
+            let frames = self.pending.len() / FRAME_SAMPLES;
+            for i in 0..frames {
+                let frame: Vec<f32> = self.pending[i * FRAME_SAMPLES..(i + 1) * FRAME_SAMPLES].to_vec();
+                self.encoded += FRAME_SAMPLES as u64;
+                // Close the page after the chunk's last frame so it can be sent right away
+                let end = if i + 1 == frames { PacketWriteEndInfo::EndPage } else { PacketWriteEndInfo::NormalPacket };
+                self.write_frame(&frame, end, self.pre_skip + self.encoded)?;
+            }
+            self.pending.drain(..frames * FRAME_SAMPLES);
+            Ok(std::mem::take(self.writer.inner_mut()))
+        }
+
+        fn finish(mut self: Box<Self>) -> Result<Vec<u8>> {
+            // The last frame is padded with silence; its granule position trims the padding
+            let mut frame = std::mem::take(&mut self.pending);
+            frame.resize(FRAME_SAMPLES, 0.0);
+            self.write_frame(&frame, PacketWriteEndInfo::EndStream, self.pre_skip + self.received)?;
+            Ok(self.writer.into_inner())
+        }
+    }
+}
+
+#[cfg(feature = "mp3")]
+mod mp3 {
+    use anyhow::{anyhow, Result};
+    use mp3lame_encoder::{Bitrate, Builder, Encoder, FlushNoGap, MonoPcm, Quality};
+
+    use super::NativeEncoder;
+    use crate::audio::sample_to_i16;
+
+    pub struct Mp3Encoder {
+        encoder: Encoder,
+    }
+
+    impl Mp3Encoder {
+        pub fn new(sample_rate: i32) -> Result<Self> {
+            let mut builder = Builder::new().ok_or_else(|| anyhow!("Failed to create LAME encoder"))?;
+            builder
+                .set_num_channels(1)
+                .map_err(|e| anyhow!("Failed to set MP3 channels: {:?}", e))?;
+            builder
+                .set_sample_rate(sample_rate as u32)
+                .map_err(|e| anyhow!("Failed to set MP3 sample rate: {:?}", e))?;
+            // Matches ffmpeg's libmp3lame default
+            builder
+                .set_brate(Bitrate::Kbps128)
+                .map_err(|e| anyhow!("Failed to set MP3 bitrate: {:?}", e))?;
+            builder
+                .set_quality(Quality::Good)
+                .map_err(|e| anyhow!("Failed to set MP3 quality: {:?}", e))?;
+            let encoder = builder.build().map_err(|e| anyhow!("Failed to initialize LAME: {:?}", e))?;
+            Ok(Mp3Encoder { encoder })
+        }
+    }
+
+    impl NativeEncoder for Mp3Encoder {
+        fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>> {
+            let pcm: Vec<i16> = samples.iter().map(|&s| sample_to_i16(s)).collect();
+            let mut out = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(pcm.len()));
+            self.encoder
+                .encode_to_vec(MonoPcm(&pcm), &mut out)
+                .map_err(|e| anyhow!("MP3 encoding failed: {:?}", e))?;
+            Ok(out)
+        }
+
+        fn finish(mut self: Box<Self>) -> Result<Vec<u8>> {
+            let mut out = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(0));
+            self.encoder
+                .flush_to_vec::<FlushNoGap>(&mut out)
+                .map_err(|e| anyhow!("MP3 encoding failed: {:?}", e))?;
+            Ok(out)
+        }
+    }
+}
@@ -0,0 +1,310 @@
+// ============================================================================
+// Single-Flight - One synthesis per input, shared by identical concurrent requests
+// ============================================================================
+//
+// The first request to miss the cache starts the synthesis; identical requests arriving
+// while it runs subscribe to it instead of starting their own. Every subscriber receives
+// all chunks from the start, so late joiners can still stream, and a failure reaches
+// each of them. Synthesis stops once every subscriber has gone away.
+
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+
+use crate::audio::PcmChunk;
+use crate::metrics::METRICS;
+
+#[derive(Default)]
+struct FlightState {
+    chunks: Vec<Vec<f32>>,
+    /// Set once synthesis ends; an error is kept as text so every subscriber gets a copy
+    outcome: Option<Result<(), String>>,
+}
+
+/// Syntheses in progress, keyed by the hash of their cached PCM
+#[derive(Default)]
+pub struct Flights {
+    active: Mutex<HashMap<String, Arc<watch::Sender<FlightState>>>>,
+}
+
+impl Flights {
+    /// Subscribe to the synthesis running for `hash`, or run `start` to begin one.
+    /// Only a new synthesis calls `admit`, and it holds what `admit` returns (its place
+    /// in the work queue) until it ends; `on_complete` gets the whole audio once it succeeds.
+    pub fn join<T, E, A, S, C, Fut>(
+        self: &Arc<Self>,
+        hash: &str,
+        admit: A,
+        start: S,
+        on_complete: C,
+    ) -> Result<mpsc::Receiver<PcmChunk>, E>
+    where
+        T: Send + 'static,
+        A: FnOnce() -> Result<T, E>,
+        S: FnOnce() -> mpsc::Receiver<PcmChunk>,
+        C: FnOnce(Vec<f32>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut active = self.active.lock().unwrap();
+        if let Some(flight) = active.get(hash) {
+            METRICS.coalesced_requests.inc();
+            return Ok(subscribe(flight.subscribe()));
+        }
+        // Admitted under the lock, so a flight ending meanwhile cannot let a request skip the queue
+        let admission = admit()?;
+
+        let (sender, receiver) = watch::channel(FlightState::default());
+        let sender = Arc::new(sender);
+        active.insert(hash.to_string(), sender.clone());
+        drop(active);
+
+        tokio::spawn(run(self.clone(), hash.to_string(), sender, admission, start(), on_complete));
+        Ok(subscribe(receiver))
+    }
+}
+
+/// Publish the chunks of `source` to the flight's subscribers
+async fn run<T, C, Fut>(
+    flights: Arc<Flights>,
+    hash: String,
+    sender: Arc<watch::Sender<FlightState>>,
+    _admission: T,
+    mut source: mpsc::Receiver<PcmChunk>,
+    on_complete: C,
+) where
+    C: FnOnce(Vec<f32>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let outcome = loop {
+        tokio::select! {
+            chunk = source.recv() => match chunk {
+                Some(Ok(samples)) => sender.send_modify(|state| state.chunks.push(samples)),
+                Some(Err(e)) => break Err(e.to_string()),
+                None => break Ok(()),
+            },
+            _ = sender.closed() => {
+                // Checked under the lock, since a request may have joined in the meantime.
+                // Dropping `source` stops the synthesis.
+                let mut active = flights.active.lock().unwrap();
+                if sender.receiver_count() == 0 {
+                    active.remove(&hash);
+                    return;
+                }
+            }
+        }
+    };
+
+    let succeeded = outcome.is_ok();
+    sender.send_modify(|state| state.outcome = Some(outcome));
+    if succeeded {
+        let samples = sender.borrow().chunks.concat();
+        on_complete(samples).await;
+    }
+    flights.active.lock().unwrap().remove(&hash);
+}
+
+/// Forward a flight's chunks, from the first one, to a channel of its own
+fn subscribe(mut receiver: watch::Receiver<FlightState>) -> mpsc::Receiver<PcmChunk> {
+    let (tx, rx) = mpsc::channel::<PcmChunk>(4);
+
+    tokio::spawn(async move {
+        let mut sent = 0;
+        loop {
+            let (chunks, outcome) = {
+                let state = receiver.borrow_and_update();
+                (state.chunks[sent..].to_vec(), state.outcome.clone())
+            };
+            sent += chunks.len();
+            for chunk in chunks {
+                if tx.send(Ok(chunk)).await.is_err() {
+                    return;
+                }
+            }
+            match outcome {
+                Some(Ok(())) => return,
+                Some(Err(e)) => {
+                    let _ = tx.send(Err(anyhow!(e))).await;
+                    return;
+                }
+                None => {}
+            }
+
+            // Leave as soon as the request is gone, so an abandoned flight can stop
+            tokio::select! {
+                changed = receiver.changed() => {
+                    if changed.is_err() {
+                        let _ = tx.send(Err(anyhow!("Task Error: synthesis ended unexpectedly"))).await;
+                        return;
+                    }
+                }
+                _ = tx.closed() => return,
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+    use tokio::time::timeout;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn admit() -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    /// `start` for requests expected to join a synthesis already running
+    fn no_start() -> mpsc::Receiver<PcmChunk> {
+        panic!("a second synthesis was started");
+    }
+
+    async fn next(rx: &mut mpsc::Receiver<PcmChunk>) -> Option<Vec<f32>> {
+        timeout(WAIT, rx.recv()).await.expect("timed out waiting for a chunk").map(|chunk| chunk.unwrap())
+    }
+
+    async fn wait_until_removed(flights: &Flights, hash: &str) {
+        timeout(WAIT, async {
+            while flights.active.lock().unwrap().contains_key(hash) {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("flight was never removed");
+    }
+
+    #[tokio::test]
+    async fn late_joiner_receives_every_chunk_from_the_start() {
+        let flights = Arc::new(Flights::default());
+        let (source_tx, source_rx) = mpsc::channel(4);
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut first = flights
+            .join("a", admit, move || source_rx, move |samples| async move {
+                let _ = done_tx.send(samples);
+            })
+            .unwrap();
+
+        source_tx.send(Ok(vec![1.0])).await.unwrap();
+        assert_eq!(next(&mut first).await, Some(vec![1.0]));
+
+        let mut second = flights.join("a", admit, no_start, |_| async {}).unwrap();
+        source_tx.send(Ok(vec![2.0])).await.unwrap();
+        drop(source_tx);
+
+        assert_eq!(next(&mut first).await, Some(vec![2.0]));
+        assert_eq!(next(&mut first).await, None);
+        assert_eq!(next(&mut second).await, Some(vec![1.0]));
+        assert_eq!(next(&mut second).await, Some(vec![2.0]));
+        assert_eq!(next(&mut second).await, None);
+        assert_eq!(timeout(WAIT, done_rx).await.unwrap().unwrap(), vec![1.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn error_reaches_every_subscriber() {
+        let flights = Arc::new(Flights::default());
+        let (source_tx, source_rx) = mpsc::channel(4);
+        let (done_tx, done_rx) = oneshot::channel::<Vec<f32>>();
+        let mut first = flights
+            .join("a", admit, move || source_rx, move |samples| async move {
+                let _ = done_tx.send(samples);
+            })
+            .unwrap();
+        let mut second = flights.join("a", admit, no_start, |_| async {}).unwrap();
+
+        source_tx.send(Err(anyhow!("engine failed"))).await.unwrap();
+        for rx in [&mut first, &mut second] {
+            let error = timeout(WAIT, rx.recv()).await.unwrap().unwrap().unwrap_err();
+            assert_eq!(error.to_string(), "engine failed");
+            assert!(timeout(WAIT, rx.recv()).await.unwrap().is_none());
+        }
+
+        // A failed synthesis is not completed, and the next request starts over
+        assert!(timeout(WAIT, done_rx).await.unwrap().is_err());
+        wait_until_removed(&flights, "a").await;
+    }
+
+    #[tokio::test]
+    async fn synthesis_stops_when_the_last_subscriber_leaves() {
+        let flights = Arc::new(Flights::default());
+        let (source_tx, source_rx) = mpsc::channel(4);
+        let first = flights.join("a", admit, move || source_rx, |_| async {}).unwrap();
+        let second = flights.join("a", admit, no_start, |_| async {}).unwrap();
+
+        drop(first);
+        source_tx.send(Ok(vec![1.0])).await.unwrap();
+        assert!(flights.active.lock().unwrap().contains_key("a"));
+
+        drop(second);
+        timeout(WAIT, source_tx.closed()).await.expect("synthesis kept running without subscribers");
+        assert!(!flights.active.lock().unwrap().contains_key("a"));
+
+        let mut started = false;
+        let (_restart_tx, restart_rx) = mpsc::channel(4);
+        let _third = flights.join("a", admit, || {
+            started = true;
+            restart_rx
+        }, |_| async {});
+        assert!(started);
+    }
+
+    #[tokio::test]
+    async fn joining_while_completing_replays_the_finished_audio() {
+        let flights = Arc::new(Flights::default());
+        let (source_tx, source_rx) = mpsc::channel(4);
+        let (completing_tx, completing_rx) = oneshot::channel();
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+        let mut first = flights
+            .join("a", admit, move || source_rx, move |_| async move {
+                let _ = completing_tx.send(());
+                let _ = release_rx.await;
+            })
+            .unwrap();
+
+        source_tx.send(Ok(vec![1.0])).await.unwrap();
+        drop(source_tx);
+        assert_eq!(next(&mut first).await, Some(vec![1.0]));
+        assert_eq!(next(&mut first).await, None);
+        timeout(WAIT, completing_rx).await.unwrap().unwrap();
+
+        // The flight is still registered while its audio is being stored
+        let mut late = flights.join("a", admit, no_start, |_| async {}).unwrap();
+        assert_eq!(next(&mut late).await, Some(vec![1.0]));
+        assert_eq!(next(&mut late).await, None);
+
+        release_tx.send(()).unwrap();
+        wait_until_removed(&flights, "a").await;
+    }
+
+    #[tokio::test]
+    async fn only_a_new_flight_is_admitted_and_holds_its_admission() {
+        let flights = Arc::new(Flights::default());
+        let admission = Arc::new(());
+        let (source_tx, source_rx) = mpsc::channel(4);
+        let mut first = flights
+            .join("a", || Ok::<_, Infallible>(admission.clone()), move || source_rx, |_| async {})
+            .unwrap();
+        let joined = flights.join("a", || Err::<(), _>("queue full"), no_start, |_| async {});
+        assert!(joined.is_ok());
+        assert_eq!(Arc::strong_count(&admission), 2);
+
+        drop(source_tx);
+        assert_eq!(next(&mut first).await, None);
+        wait_until_removed(&flights, "a").await;
+        assert_eq!(Arc::strong_count(&admission), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_admission_starts_nothing() {
+        let flights = Arc::new(Flights::default());
+        let joined = flights.join("a", || Err::<(), _>("queue full"), no_start, |_| async {});
+        assert_eq!(joined.err(), Some("queue full"));
+        assert!(!flights.active.lock().unwrap().contains_key("a"));
+    }
+}
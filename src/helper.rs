@@ -2,15 +2,21 @@
 // TTS Helper Module - All utility functions and structures
 // ============================================================================
 
-use ndarray::{Array, Array3};
+use ndarray::{concatenate, Array, Array3, Axis};
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 use anyhow::{Result, Context, bail};
 use unicode_normalization::UnicodeNormalization;
+use rand::{rngs::StdRng, SeedableRng};
 use rand_distr::{Distribution, Normal};
 use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::cache::{sentence_key, SentenceCache};
 
 // Available languages for multilingual TTS
 pub const AVAILABLE_LANGS: &[&str] = &["en", "ko", "es", "pt", "fr"];
@@ -232,13 +238,42 @@ pub fn get_text_mask(text_ids_lengths: &[usize]) -> Array3<f32> {
     length_to_mask(text_ids_lengths, Some(max_len))
 }
 
-/// Sample noisy latent from normal distribution and apply mask
+/// Derive the seed for item `index` from a parent seed
+pub fn derive_seed(seed: u64, index: usize) -> u64 {
+    // splitmix64 finalizer, so neighbouring indices get unrelated seeds
+    let mut z = seed.wrapping_add((index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Seed for one chunk, derived from its normalized text rather than its position,
+/// so a chunk's audio does not change when the text around it is edited
+pub fn chunk_seed(seed: u64, normalized: &str) -> u64 {
+    let digest = Sha256::digest(normalized.as_bytes());
+    derive_seed(seed ^ u64::from_le_bytes(digest[..8].try_into().unwrap()), 0)
+}
+
+/// Seed used when a request does not give one, derived from the voice and settings.
+/// The text is left out since chunk seeds already depend on it, so an edited text keeps
+/// the seeds, and the cached audio, of its unchanged chunks.
+pub fn default_seed(voice_name: &str, speed: f32, total_step: usize, lang: &str) -> u64 {
+    let key = format!("{}:{:.2}:{}:{}", voice_name, speed, total_step, lang);
+    let digest = Sha256::digest(key.as_bytes());
+    u64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Sample noisy latent from normal distribution and apply mask.
+///
+/// Each row draws only its own length from an RNG seeded with its entry in `seeds`,
+/// so a row's noise does not depend on the other rows of the batch.
 pub fn sample_noisy_latent(
     duration: &[f32],
     sample_rate: i32,
     base_chunk_size: i32,
     chunk_compress: i32,
     latent_dim: i32,
+    seeds: &[u64],
 ) -> (Array3<f32>, Array3<f32>) {
     let bsz = duration.len();
     let max_dur = duration.iter().fold(0.0f32, |a, &b| a.max(b));
@@ -255,22 +290,22 @@ pub fn sample_noisy_latent(
 
     let mut noisy_latent = Array3::<f32>::zeros((bsz, latent_dim_val, latent_len));
 
+    let latent_lengths: Vec<usize> = wav_lengths
+        .iter()
+        .map(|&len| (len + chunk_size - 1) / chunk_size)
+        .collect();
+
     let normal = Normal::new(0.0, 1.0).unwrap();
-    let mut rng = rand::thread_rng();
 
     for b in 0..bsz {
+        let mut rng = StdRng::seed_from_u64(seeds[b]);
         for d in 0..latent_dim_val {
-            for t in 0..latent_len {
+            for t in 0..latent_lengths[b] {
                 noisy_latent[[b, d, t]] = normal.sample(&mut rng);
             }
         }
     }
 
-    let latent_lengths: Vec<usize> = wav_lengths
-        .iter()
-        .map(|&len| (len + chunk_size - 1) / chunk_size)
-        .collect();
-
     let latent_mask = length_to_mask(&latent_lengths, Some(latent_len));
 
     // Apply mask
@@ -424,7 +459,22 @@ pub fn chunk_text(text: &str, max_len: Option<usize>) -> Vec<String> {
     }
 }
 
-fn split_sentences(text: &str) -> Vec<String> {
+/// Chunk text with the length limit used for the given language
+pub fn chunk_text_for_lang(text: &str, lang: &str) -> Vec<String> {
+    let max_len = if lang == "ko" { 120 } else { MAX_CHUNK_LENGTH };
+    chunk_text(text, Some(max_len))
+}
+
+/// Whether `text` ends at a sentence boundary as `split_sentences` sees it:
+/// closing punctuation that is not part of an abbreviation, then whitespace
+pub fn ends_sentence(text: &str) -> bool {
+    let trimmed = text.trim_end();
+    trimmed.len() < text.len()
+        && trimmed.ends_with(['.', '!', '?'])
+        && !ABBREVIATIONS.iter().any(|abbrev| trimmed.ends_with(abbrev))
+}
+
+pub fn split_sentences(text: &str) -> Vec<String> {
     // Rust's regex doesn't support lookbehind, so we use a simpler approach
     // Split on sentence boundaries and then check if they're abbreviations
     let re = Regex::new(r"([.!?])\s+").unwrap();
@@ -491,6 +541,109 @@ pub struct Style {
     pub dp: Array3<f32>,
 }
 
+impl Style {
+    /// Weighted average of styles with identical shapes; weights are normalized to sum to 1
+    pub fn blend(components: &[(&Style, f32)]) -> Result<Style> {
+        let (first, _) = components.first().context("Cannot blend an empty set of styles")?;
+        let total: f32 = components.iter().map(|(_, w)| *w).sum();
+        if !total.is_finite() || total <= 0.0 || components.iter().any(|(_, w)| !w.is_finite() || *w < 0.0) {
+            bail!("Blend weights must be non-negative and sum to more than 0");
+        }
+
+        let mut ttl = Array3::<f32>::zeros(first.ttl.raw_dim());
+        let mut dp = Array3::<f32>::zeros(first.dp.raw_dim());
+        for (style, weight) in components {
+            if style.ttl.shape() != first.ttl.shape() || style.dp.shape() != first.dp.shape() {
+                bail!("Cannot blend styles with different shapes");
+            }
+            ttl.scaled_add(weight / total, &style.ttl);
+            dp.scaled_add(weight / total, &style.dp);
+        }
+
+        Ok(Style { ttl, dp })
+    }
+
+    /// SHA-256 of the tensor shapes and values, identifying the voice regardless of its name
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        for tensor in [&self.ttl, &self.dp] {
+            for &dim in tensor.shape() {
+                hasher.update((dim as u64).to_le_bytes());
+            }
+            for value in tensor.iter() {
+                hasher.update(value.to_le_bytes());
+            }
+        }
+        hex::encode(hasher.finalize())
+    }
+
+    /// Stack single styles into one batched style, one row per entry
+    pub fn stack(styles: &[&Style]) -> Result<Style> {
+        let ttl: Vec<_> = styles.iter().map(|s| s.ttl.view()).collect();
+        let dp: Vec<_> = styles.iter().map(|s| s.dp.view()).collect();
+        Ok(Style {
+            ttl: concatenate(Axis(0), &ttl).context("Cannot stack styles with different shapes")?,
+            dp: concatenate(Axis(0), &dp).context("Cannot stack styles with different shapes")?,
+        })
+    }
+
+    /// Convert back to the on-disk JSON layout read by `load_voice_style`
+    pub fn to_voice_style_data(&self) -> VoiceStyleData {
+        fn component(array: &Array3<f32>) -> StyleComponent {
+            StyleComponent {
+                data: array
+                    .outer_iter()
+                    .map(|matrix| matrix.outer_iter().map(|row| row.to_vec()).collect())
+                    .collect(),
+                dims: array.shape().to_vec(),
+                dtype: "float32".to_string(),
+            }
+        }
+
+        VoiceStyleData {
+            style_ttl: component(&self.ttl),
+            style_dp: component(&self.dp),
+        }
+    }
+}
+
+/// One row of a batched synthesis; rows may use different voices and speeds
+pub struct BatchRow<'a> {
+    pub text: &'a str,
+    pub lang: &'a str,
+    pub style: &'a Style,
+    pub speed: f32,
+    /// Seeds the row's initial noise; equal seeds give identical audio
+    pub seed: u64,
+}
+
+/// Shared flag asking a synthesis to stop; checked between chunks and denoising steps
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Cancel once the returned guard drops, however its owner exits
+    pub fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop(self.clone())
+    }
+}
+
+pub struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
 pub struct TextToSpeech {
     cfgs: Config,
     text_processor: UnicodeProcessor,
@@ -499,6 +652,10 @@ pub struct TextToSpeech {
     vector_est_ort: Session,
     vocoder_ort: Session,
     pub sample_rate: i32,
+    /// Chunks of one `call` synthesized together; 1 runs them one at a time
+    chunk_batch_size: usize,
+    /// Chunks already synthesized, shared by every engine
+    sentences: Option<Arc<SentenceCache>>,
 }
 
 impl TextToSpeech {
@@ -519,18 +676,43 @@ impl TextToSpeech {
             vector_est_ort,
             vocoder_ort,
             sample_rate,
+            chunk_batch_size: 1,
+            sentences: None,
         }
     }
 
+    /// Let `call` run up to `size` chunks of its text through the models at once
+    pub fn set_chunk_batch_size(&mut self, size: usize) {
+        self.chunk_batch_size = size.max(1);
+    }
+
+    /// Let `call` reuse chunks from `cache` and store the ones it synthesizes
+    pub fn set_sentence_cache(&mut self, cache: Arc<SentenceCache>) {
+        self.sentences = Some(cache);
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.cfgs
+    }
+
+    /// Run the full pipeline on a batch; `style`, `speeds` and `seeds` have one row per text.
+    /// Returns each row's waveform trimmed to its predicted duration, plus the durations.
+    /// Stops with an error before any denoising step once `cancelled` returns true.
+    #[allow(clippy::too_many_arguments)]
     fn _infer(
         &mut self,
         text_list: &[String],
         lang_list: &[String],
         style: &Style,
         total_step: usize,
-        speed: f32,
-    ) -> Result<(Vec<f32>, Vec<f32>)> {
+        speeds: &[f32],
+        seeds: &[u64],
+        cancelled: &dyn Fn() -> bool,
+    ) -> Result<(Vec<Vec<f32>>, Vec<f32>)> {
         let bsz = text_list.len();
+        if cancelled() {
+            bail!("Synthesis cancelled");
+        }
 
         // Process text
         let (text_ids, text_mask) = self.text_processor.call(text_list, lang_list)?;
@@ -558,8 +740,8 @@ impl TextToSpeech {
         let (_, duration_data) = dp_outputs["duration"].try_extract_tensor::<f32>()?;
         let mut duration: Vec<f32> = duration_data.to_vec();
         
-        // Apply speed factor to duration
-        for dur in duration.iter_mut() {
+        // Apply each row's speed factor to its duration
+        for (dur, speed) in duration.iter_mut().zip(speeds) {
             *dur /= speed;
         }
 
@@ -584,6 +766,7 @@ impl TextToSpeech {
             self.cfgs.ae.base_chunk_size,
             self.cfgs.ttl.chunk_compress_factor,
             self.cfgs.ttl.latent_dim,
+            seeds,
         );
 
         // Prepare constant arrays
@@ -591,6 +774,9 @@ impl TextToSpeech {
 
         // Denoising loop
         for step in 0..total_step {
+            if cancelled() {
+                bail!("Synthesis cancelled");
+            }
             let current_step_array = Array::from_elem(bsz, step as f32);
 
             let xt_value = Value::from_array(xt.clone())?;
@@ -623,98 +809,147 @@ impl TextToSpeech {
             "latent" => &final_latent_value
         })?;
 
+        // Rows are padded to the longest one; cut each back to its own duration
         let (_, wav_data) = vocoder_outputs["wav_tts"].try_extract_tensor::<f32>()?;
-        let wav: Vec<f32> = wav_data.to_vec();
+        let row_len = wav_data.len() / bsz;
+        let wavs: Vec<Vec<f32>> = duration
+            .iter()
+            .enumerate()
+            .map(|(i, &dur)| {
+                let wav_len = ((self.sample_rate as f32 * dur) as usize).min(row_len);
+                wav_data[i * row_len..i * row_len + wav_len].to_vec()
+            })
+            .collect();
 
-        Ok((wav, duration))
+        Ok((wavs, duration))
     }
 
-        pub fn call(
-
-            &mut self,
-
-            text: &str,
-
-            lang: &str,
-
-            style: &Style,
-
-            total_step: usize,
-
-            speed: f32,
-
-            silence_duration: f32,
-
-        ) -> Result<(Vec<f32>, f32)> {
-
-            let max_len = if lang == "ko" { 120 } else { 300 };
-
-            let chunks = chunk_text(text, Some(max_len));
-
-            
-
-            let mut wav_cat: Vec<f32> = Vec::new();
-
-            let mut dur_cat: f32 = 0.0;
-
-    
-
-            for (i, chunk) in chunks.iter().enumerate() {
-
-                let (wav, duration) = self._infer(&[chunk.clone()], &[lang.to_string()], style, total_step, speed)?;
-
-                
-
-                let dur = duration[0];
-
-                let wav_len = (self.sample_rate as f32 * dur) as usize;
-
-                let wav_chunk = &wav[..wav_len.min(wav.len())];
-
-    
-
-                if i == 0 {
-
-                    wav_cat.extend_from_slice(wav_chunk);
-
-                    dur_cat = dur;
-
-                } else {
-
-                    let silence_len = (silence_duration * self.sample_rate as f32) as usize;
-
-                    let silence = vec![0.0f32; silence_len];
-
-                    
-
-                    wav_cat.extend_from_slice(&silence);
+    /// Synthesize a single chunk and trim the vocoder output to its predicted duration
+    #[allow(clippy::too_many_arguments)]
+    pub fn synthesize_chunk(
+        &mut self,
+        chunk: &str,
+        lang: &str,
+        style: &Style,
+        total_step: usize,
+        speed: f32,
+        seed: u64,
+        cancel: &CancelToken,
+    ) -> Result<(Vec<f32>, f32)> {
+        let (mut wavs, duration) = self._infer(
+            &[chunk.to_string()],
+            &[lang.to_string()],
+            style,
+            total_step,
+            &[speed],
+            &[seed],
+            &|| cancel.is_cancelled(),
+        )?;
+        Ok((wavs.remove(0), duration[0]))
+    }
 
-                    wav_cat.extend_from_slice(wav_chunk);
+    /// Synthesize several chunks in one pass through the models.
+    /// Every row shares `total_step`, which drives the denoising loop.
+    /// `cancelled` stops the whole batch, so it should only return true once no row is wanted.
+    pub fn synthesize_batch(
+        &mut self,
+        rows: &[BatchRow],
+        total_step: usize,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Result<Vec<(Vec<f32>, f32)>> {
+        let text_list: Vec<String> = rows.iter().map(|row| row.text.to_string()).collect();
+        let lang_list: Vec<String> = rows.iter().map(|row| row.lang.to_string()).collect();
+        let styles: Vec<&Style> = rows.iter().map(|row| row.style).collect();
+        let speeds: Vec<f32> = rows.iter().map(|row| row.speed).collect();
+        let seeds: Vec<u64> = rows.iter().map(|row| row.seed).collect();
+
+        let style = Style::stack(&styles)?;
+        let (wavs, duration) = self._infer(&text_list, &lang_list, &style, total_step, &speeds, &seeds, cancelled)?;
+        Ok(wavs.into_iter().zip(duration).collect())
+    }
 
-                    dur_cat += silence_duration + dur;
+    /// Synthesize `text` chunk by chunk, each seeded with `chunk_seed`.
+    /// Chunks found in the sentence cache are reused; only the others go through the models.
+    /// `cancel` is checked between chunks and between denoising steps.
+    #[allow(clippy::too_many_arguments)]
+    pub fn call(
+        &mut self,
+        text: &str,
+        lang: &str,
+        style: &Style,
+        total_step: usize,
+        speed: f32,
+        silence_duration: f32,
+        seed: u64,
+        cancel: &CancelToken,
+    ) -> Result<(Vec<f32>, f32)> {
+        let chunks = chunk_text_for_lang(text, lang);
+        let sentences = self.sentences.clone();
+        let style_fingerprint = sentences.as_ref().map(|_| style.fingerprint());
+
+        // (chunk index, seed, sentence cache key) of every chunk that needs synthesis
+        let mut missing = Vec::new();
+        let mut outputs: Vec<Option<(Vec<f32>, f32)>> = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            let normalized = preprocess_text(chunk, lang)?;
+            let seed = chunk_seed(seed, &normalized);
+            let key = style_fingerprint.as_deref().map(|fp| sentence_key(&normalized, fp, speed, total_step, seed));
+            let cached = sentences.as_ref().zip(key.as_deref()).and_then(|(cache, key)| cache.get(key));
+            if cached.is_none() {
+                missing.push((i, seed, key));
+            }
+            outputs.push(cached);
+        }
 
+        // Each batch is padded to its longest chunk, and each row is trimmed to its own duration
+        for batch in missing.chunks(self.chunk_batch_size) {
+            if cancel.is_cancelled() {
+                bail!("Synthesis cancelled");
+            }
+            let results = if let [(i, seed, _)] = batch {
+                vec![self.synthesize_chunk(&chunks[*i], lang, style, total_step, speed, *seed, cancel)?]
+            } else {
+                let rows: Vec<BatchRow> = batch
+                    .iter()
+                    .map(|(i, seed, _)| BatchRow { text: &chunks[*i], lang, style, speed, seed: *seed })
+                    .collect();
+                self.synthesize_batch(&rows, total_step, &|| cancel.is_cancelled())?
+            };
+            for ((i, _, key), (wav, dur)) in batch.iter().zip(results) {
+                if let Some((cache, key)) = sentences.as_ref().zip(key.as_deref()) {
+                    cache.insert(key, &wav, dur);
                 }
-
+                outputs[*i] = Some((wav, dur));
             }
+        }
 
-    
+        let mut wav_cat: Vec<f32> = Vec::new();
+        let mut dur_cat: f32 = 0.0;
 
-            Ok((wav_cat, dur_cat))
+        for (i, (wav, dur)) in outputs.into_iter().flatten().enumerate() {
+            if i == 0 {
+                wav_cat.extend_from_slice(&wav);
+                dur_cat = dur;
+            } else {
+                let silence_len = (silence_duration * self.sample_rate as f32) as usize;
+                let silence = vec![0.0f32; silence_len];
 
+                wav_cat.extend_from_slice(&silence);
+                wav_cat.extend_from_slice(&wav);
+                dur_cat += silence_duration + dur;
+            }
         }
 
+        Ok((wav_cat, dur_cat))
     }
+}
 
+// ============================================================================
+// Component Loading Functions
+// ============================================================================
 
-    // ============================================================================
-
-    // Component Loading Functions
-
-    // ============================================================================
-
-    /// Load voice style from JSON files
-
-    pub fn load_voice_style(voice_style_paths: &[String], verbose: bool) -> Result<Style> {
+/// Load voice style from JSON files
+pub fn load_voice_style(voice_style_paths: &[String], verbose: bool) -> Result<Style> {
     let bsz = voice_style_paths.len();
 
     // Read first file to get dimensions
@@ -725,6 +960,9 @@ impl TextToSpeech {
 
     let ttl_dims = &first_data.style_ttl.dims;
     let dp_dims = &first_data.style_dp.dims;
+    if ttl_dims.len() != 3 || dp_dims.len() != 3 {
+        bail!("Voice style dims must have 3 entries, got {:?} and {:?}", ttl_dims, dp_dims);
+    }
 
     let ttl_dim1 = ttl_dims[1];
     let ttl_dim2 = ttl_dims[2];
@@ -742,6 +980,8 @@ impl TextToSpeech {
         let file = File::open(path).context("Failed to open voice style file")?;
         let reader = BufReader::new(file);
         let data: VoiceStyleData = serde_json::from_reader(reader)?;
+        validate_style_component("style_ttl", &data.style_ttl, ttl_dim1, ttl_dim2)?;
+        validate_style_component("style_dp", &data.style_dp, dp_dim1, dp_dim2)?;
 
         // Flatten TTL data
         let ttl_offset = i * ttl_dim1 * ttl_dim2;
@@ -781,6 +1021,43 @@ impl TextToSpeech {
     })
 }
 
+/// Check that a style component holds exactly one `dim1 x dim2` matrix of finite values
+fn validate_style_component(name: &str, component: &StyleComponent, dim1: usize, dim2: usize) -> Result<()> {
+    if component.dims.len() != 3 || component.dims[1] != dim1 || component.dims[2] != dim2 {
+        bail!("{} dims {:?} do not match [_, {}, {}]", name, component.dims, dim1, dim2);
+    }
+    let count: usize = component.data.iter().flatten().map(|row| row.len()).sum();
+    if count != dim1 * dim2 {
+        bail!("{} has {} values, expected {}", name, count, dim1 * dim2);
+    }
+    if component.data.iter().flatten().flatten().any(|v| !v.is_finite()) {
+        bail!("{} contains non-finite values", name);
+    }
+    Ok(())
+}
+
+/// Files read by `load_text_to_speech`, relative to the ONNX directory
+const MODEL_FILES: &[&str] = &[
+    "tts.json",
+    "unicode_indexer.json",
+    "duration_predictor.onnx",
+    "text_encoder.onnx",
+    "vector_estimator.onnx",
+    "vocoder.onnx",
+];
+
+/// SHA-256 over every model file, so outputs can be tied to the exact models that made them
+pub fn model_fingerprint<P: AsRef<Path>>(onnx_dir: P) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for name in MODEL_FILES {
+        let path = onnx_dir.as_ref().join(name);
+        let mut file = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        hasher.update(name.as_bytes());
+        std::io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read {}", path.display()))?;
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
 /// Load TTS components
 pub fn load_text_to_speech(onnx_dir: &str, use_gpu: bool) -> Result<TextToSpeech> {
     if use_gpu {
@@ -0,0 +1,187 @@
+// ============================================================================
+// Metrics - Prometheus exposition on /metrics
+// ============================================================================
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Process-wide metrics; recorded from handlers, synthesis threads and the encoders alike
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// Speech requests by voice, format and HTTP status
+    pub requests: IntCounterVec,
+    /// Wall time of one pass through the models (a chunk, or a batch of chunks)
+    pub synthesis_seconds: Histogram,
+    /// Synthesis time divided by the duration of the audio produced
+    pub real_time_factor: Histogram,
+    pub characters: IntCounter,
+    /// Encoded audio served from memory or disk
+    pub cache_hits: IntCounter,
+    /// Requests needing synthesis
+    pub cache_misses: IntCounter,
+    /// Requests encoded from cached PCM without synthesis
+    pub cache_transcodes: IntCounter,
+    /// Speech requests that joined an identical synthesis already in progress
+    pub coalesced_requests: IntCounter,
+    /// Cache files that failed verification and were discarded
+    pub cache_corrupt: IntCounter,
+    pub memory_cache_hits: IntCounter,
+    pub memory_cache_evictions: IntCounter,
+    pub memory_cache_size_bytes: IntGauge,
+    pub memory_cache_entries: IntGauge,
+    /// Chunks reused from the sentence cache instead of being synthesized
+    pub sentence_cache_hits: IntCounter,
+    pub sentence_cache_misses: IntCounter,
+    pub sentence_cache_size_bytes: IntGauge,
+    /// Size of the disk cache as of the last pruning run
+    pub cache_size_bytes: IntGauge,
+    pub ffmpeg_failures: IntCounter,
+    /// Time spent waiting to check out a TTS engine
+    pub tts_lock_wait_seconds: Histogram,
+    /// Requests admitted to the work queue and not yet finished
+    pub queue_depth: IntGauge,
+    /// Requests turned away with 503 because the queue was full, by priority class
+    pub queue_rejections: IntCounterVec,
+    /// Speech requests cancelled because their deadline passed
+    pub request_timeouts: IntCounter,
+    /// Chunks per batch when cross-request batching is enabled
+    pub batch_size: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("supertonic".to_string()), None)
+            .expect("valid metrics prefix");
+
+        let requests = IntCounterVec::new(
+            Opts::new("speech_requests_total", "Speech requests by voice, format and status"),
+            &["voice", "format", "status"],
+        )
+        .unwrap();
+        let synthesis_seconds = Histogram::with_opts(
+            HistogramOpts::new("synthesis_seconds", "Time of one pass through the models, for a chunk or a batch")
+                .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0]),
+        )
+        .unwrap();
+        let real_time_factor = Histogram::with_opts(
+            HistogramOpts::new(
+                "synthesis_real_time_factor",
+                "Synthesis time divided by the duration of the audio produced",
+            )
+            .buckets(vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0]),
+        )
+        .unwrap();
+        let characters = IntCounter::new("characters_synthesized_total", "Input characters synthesized").unwrap();
+        let cache_hits = IntCounter::new("cache_hits_total", "Speech requests served from the cache").unwrap();
+        let cache_misses = IntCounter::new("cache_misses_total", "Speech requests not found in the cache").unwrap();
+        let cache_transcodes = IntCounter::new("cache_transcodes_total", "Speech requests encoded from cached PCM").unwrap();
+        let coalesced_requests = IntCounter::new("coalesced_requests_total", "Speech requests that joined an identical synthesis in progress").unwrap();
+        let cache_corrupt = IntCounter::new("cache_corrupt_total", "Cache files discarded because they failed verification").unwrap();
+        let memory_cache_hits = IntCounter::new("memory_cache_hits_total", "Speech requests served from the in-memory cache").unwrap();
+        let memory_cache_evictions = IntCounter::new("memory_cache_evictions_total", "Entries evicted from the in-memory cache").unwrap();
+        let memory_cache_size_bytes = IntGauge::new("memory_cache_size_bytes", "Audio bytes held in the in-memory cache").unwrap();
+        let memory_cache_entries = IntGauge::new("memory_cache_entries", "Entries held in the in-memory cache").unwrap();
+        let sentence_cache_hits = IntCounter::new("sentence_cache_hits_total", "Chunks reused from the sentence cache").unwrap();
+        let sentence_cache_misses = IntCounter::new("sentence_cache_misses_total", "Chunks not found in the sentence cache").unwrap();
+        let sentence_cache_size_bytes = IntGauge::new("sentence_cache_size_bytes", "Bytes of samples held in the sentence cache").unwrap();
+        let cache_size_bytes = IntGauge::new("cache_size_bytes", "Disk cache size after the last pruning run").unwrap();
+        let ffmpeg_failures = IntCounter::new("ffmpeg_failures_total", "ffmpeg processes that failed to start or exited with an error").unwrap();
+        let tts_lock_wait_seconds = Histogram::with_opts(
+            HistogramOpts::new("tts_lock_wait_seconds", "Time spent waiting for a free TTS engine")
+                .buckets(vec![0.001, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0]),
+        )
+        .unwrap();
+        let queue_depth = IntGauge::new("queue_depth", "Speech requests admitted to the work queue").unwrap();
+        let queue_rejections = IntCounterVec::new(
+            Opts::new("queue_rejections_total", "Speech requests rejected because the work queue was full"),
+            &["priority"],
+        )
+        .unwrap();
+        let request_timeouts = IntCounter::new("request_timeouts_total", "Speech requests cancelled because their deadline passed").unwrap();
+        let batch_size = Histogram::with_opts(
+            HistogramOpts::new("batch_size", "Chunks synthesized together in one batch")
+                .buckets(vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0]),
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(synthesis_seconds.clone())).unwrap();
+        registry.register(Box::new(real_time_factor.clone())).unwrap();
+        registry.register(Box::new(characters.clone())).unwrap();
+        registry.register(Box::new(cache_hits.clone())).unwrap();
+        registry.register(Box::new(cache_misses.clone())).unwrap();
+        registry.register(Box::new(cache_transcodes.clone())).unwrap();
+        registry.register(Box::new(coalesced_requests.clone())).unwrap();
+        registry.register(Box::new(cache_corrupt.clone())).unwrap();
+        registry.register(Box::new(memory_cache_hits.clone())).unwrap();
+        registry.register(Box::new(memory_cache_evictions.clone())).unwrap();
+        registry.register(Box::new(memory_cache_size_bytes.clone())).unwrap();
+        registry.register(Box::new(memory_cache_entries.clone())).unwrap();
+        registry.register(Box::new(sentence_cache_hits.clone())).unwrap();
+        registry.register(Box::new(sentence_cache_misses.clone())).unwrap();
+        registry.register(Box::new(sentence_cache_size_bytes.clone())).unwrap();
+        registry.register(Box::new(cache_size_bytes.clone())).unwrap();
+        registry.register(Box::new(ffmpeg_failures.clone())).unwrap();
+        registry.register(Box::new(tts_lock_wait_seconds.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(queue_rejections.clone())).unwrap();
+        registry.register(Box::new(request_timeouts.clone())).unwrap();
+        registry.register(Box::new(batch_size.clone())).unwrap();
+
+        Metrics {
+            registry,
+            requests,
+            synthesis_seconds,
+            real_time_factor,
+            characters,
+            cache_hits,
+            cache_misses,
+            cache_transcodes,
+            coalesced_requests,
+            cache_corrupt,
+            memory_cache_hits,
+            memory_cache_evictions,
+            memory_cache_size_bytes,
+            memory_cache_entries,
+            sentence_cache_hits,
+            sentence_cache_misses,
+            sentence_cache_size_bytes,
+            cache_size_bytes,
+            ffmpeg_failures,
+            tts_lock_wait_seconds,
+            queue_depth,
+            queue_rejections,
+            request_timeouts,
+            batch_size,
+        }
+    }
+
+    /// Record one synthesis pass over `chars` characters producing `audio_secs` of audio
+    pub fn observe_synthesis(&self, elapsed: Duration, audio_secs: f32, chars: usize) {
+        let elapsed = elapsed.as_secs_f64();
+        self.synthesis_seconds.observe(elapsed);
+        if audio_secs > 0.0 {
+            self.real_time_factor.observe(elapsed / audio_secs as f64);
+        }
+        self.characters.inc_by(chars as u64);
+    }
+}
+
+pub async fn metrics_handler() -> Response {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut body) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Metrics Error: {}", e)).into_response();
+    }
+    ([(header::CONTENT_TYPE, encoder.format_type().to_string())], body).into_response()
+}
@@ -0,0 +1,90 @@
+// ============================================================================
+// Models - OpenAI-compatible model listing
+// ============================================================================
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::helper::{Config, AVAILABLE_LANGS};
+use crate::{openai_error, AppState};
+
+/// Canonical ID of the loaded engine
+pub const MODEL_ID: &str = "supertonic-2";
+
+/// OpenAI model names accepted as aliases of [`MODEL_ID`]
+pub const MODEL_ALIASES: &[&str] = &["tts-1"];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ModelInfo {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub owned_by: &'static str,
+    /// Model this entry resolves to; equal to `id` for the canonical entry
+    pub root: String,
+    pub sample_rate: i32,
+    pub languages: Vec<&'static str>,
+}
+
+#[derive(Serialize, Debug)]
+struct ModelList<'a> {
+    object: &'static str,
+    data: &'a [ModelInfo],
+}
+
+/// Build the model list for the engine loaded with `cfgs`
+pub fn loaded_models(cfgs: &Config) -> Vec<ModelInfo> {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    std::iter::once(MODEL_ID)
+        .chain(MODEL_ALIASES.iter().copied())
+        .map(|id| ModelInfo {
+            id: id.to_string(),
+            object: "model",
+            created,
+            owned_by: "supertone",
+            root: MODEL_ID.to_string(),
+            sample_rate: cfgs.ae.sample_rate,
+            languages: AVAILABLE_LANGS.to_vec(),
+        })
+        .collect()
+}
+
+/// Response for a model that is not loaded, matching OpenAI's `model_not_found` error
+pub fn model_not_found(model: &str) -> Response {
+    openai_error(
+        StatusCode::NOT_FOUND,
+        &format!("The model '{}' does not exist", model),
+        "invalid_request_error",
+        Some("model"),
+        Some("model_not_found"),
+    )
+}
+
+pub async fn list_models(State(state): State<Arc<AppState>>) -> Response {
+    Json(ModelList {
+        object: "list",
+        data: &state.models,
+    })
+    .into_response()
+}
+
+pub async fn retrieve_model(
+    State(state): State<Arc<AppState>>,
+    Path(model): Path<String>,
+) -> Response {
+    match state.models.iter().find(|m| m.id == model) {
+        Some(info) => Json(info).into_response(),
+        None => model_not_found(&model),
+    }
+}
@@ -0,0 +1,156 @@
+// ============================================================================
+// Engine Pool - Several TextToSpeech instances with priority-ordered checkout
+// ============================================================================
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::cache::SentenceCache;
+use crate::helper::{load_text_to_speech, model_fingerprint, Config, TextToSpeech};
+use crate::metrics::METRICS;
+use crate::queue::Priority;
+
+/// Engine time per input character assumed until a synthesis has been measured
+const INITIAL_SECS_PER_CHAR: f64 = 0.02;
+/// Weight of the latest measurement in the moving average
+const SECS_PER_CHAR_SMOOTHING: f64 = 0.1;
+
+struct PoolInner {
+    idle: Vec<TextToSpeech>,
+    /// Ticket handed to the next caller of `checkout`
+    next_ticket: u64,
+    /// Callers waiting for an engine, served in this order
+    waiting: BTreeSet<(Priority, u64)>,
+}
+
+/// A fixed set of engines, each with its own ORT sessions.
+///
+/// Callers are served by priority class, then strictly in arrival order, so a request
+/// splitting its work into many checkouts cannot starve others of its class that queued
+/// in between.
+pub struct EnginePool {
+    inner: Mutex<PoolInner>,
+    available: Condvar,
+    size: usize,
+    chunk_batch_size: usize,
+    /// Shared by every engine, so it can be read without a checkout
+    pub sample_rate: i32,
+    /// Hash of the loaded model files, part of every cache key
+    pub model_fingerprint: String,
+    cfgs: Config,
+    /// Moving average of engine time per input character
+    secs_per_char: Mutex<f64>,
+}
+
+impl EnginePool {
+    pub fn load(onnx_dir: &str, size: usize, chunk_batch_size: usize, sentences: Option<Arc<SentenceCache>>) -> Result<Self> {
+        let engines = (0..size)
+            .map(|_| {
+                let mut engine = load_text_to_speech(onnx_dir, false)?;
+                engine.set_chunk_batch_size(chunk_batch_size);
+                if let Some(ref sentences) = sentences {
+                    engine.set_sentence_cache(sentences.clone());
+                }
+                Ok(engine)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let sample_rate = engines[0].sample_rate;
+        let cfgs = engines[0].config().clone();
+        let model_fingerprint = model_fingerprint(onnx_dir)?;
+
+        Ok(EnginePool {
+            inner: Mutex::new(PoolInner {
+                idle: engines,
+                next_ticket: 0,
+                waiting: BTreeSet::new(),
+            }),
+            available: Condvar::new(),
+            size,
+            chunk_batch_size,
+            sample_rate,
+            model_fingerprint,
+            cfgs,
+            secs_per_char: Mutex::new(INITIAL_SECS_PER_CHAR),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn chunk_batch_size(&self) -> usize {
+        self.chunk_batch_size
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.cfgs
+    }
+
+    /// Engine time per input character, averaged over recent syntheses
+    pub fn secs_per_char(&self) -> f64 {
+        *self.secs_per_char.lock().unwrap()
+    }
+
+    /// Record one synthesis pass, in the metrics and in the speed estimate
+    pub fn observe_synthesis(&self, elapsed: Duration, audio_secs: f32, chars: usize) {
+        METRICS.observe_synthesis(elapsed, audio_secs, chars);
+        if chars > 0 {
+            let mut average = self.secs_per_char.lock().unwrap();
+            let latest = elapsed.as_secs_f64() / chars as f64;
+            *average += (latest - *average) * SECS_PER_CHAR_SMOOTHING;
+        }
+    }
+
+    /// Block until an engine is free and it is this caller's turn.
+    /// Call from a blocking thread; the engine returns to the pool when the guard drops.
+    pub fn checkout(&self, priority: Priority) -> EngineGuard<'_> {
+        let started = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        let place = (priority, inner.next_ticket);
+        inner.next_ticket += 1;
+        inner.waiting.insert(place);
+
+        while inner.waiting.first() != Some(&place) || inner.idle.is_empty() {
+            inner = self.available.wait(inner).unwrap();
+        }
+        let engine = inner.idle.pop();
+        inner.waiting.remove(&place);
+        drop(inner);
+        // The next ticket may be able to take another idle engine right away
+        self.available.notify_all();
+
+        METRICS.tts_lock_wait_seconds.observe(started.elapsed().as_secs_f64());
+        EngineGuard { pool: self, engine }
+    }
+}
+
+pub struct EngineGuard<'a> {
+    pool: &'a EnginePool,
+    engine: Option<TextToSpeech>,
+}
+
+impl Deref for EngineGuard<'_> {
+    type Target = TextToSpeech;
+
+    fn deref(&self) -> &TextToSpeech {
+        self.engine.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for EngineGuard<'_> {
+    fn deref_mut(&mut self) -> &mut TextToSpeech {
+        self.engine.as_mut().unwrap()
+    }
+}
+
+impl Drop for EngineGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(engine) = self.engine.take() {
+            self.pool.inner.lock().unwrap().idle.push(engine);
+            self.pool.available.notify_all();
+        }
+    }
+}
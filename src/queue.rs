@@ -0,0 +1,148 @@
+// ============================================================================
+// Work Queue - Admission control and priority classes in front of the engines
+// ============================================================================
+//
+// A request that needs synthesis is admitted first and counted until its synthesis ends.
+// Once `--queue-max-depth` requests are admitted, new ones get a 503 right away, with
+// `Retry-After` estimated from the queued input and the measured synthesis speed.
+// Batch requests are turned away earlier, at three quarters of the depth, to keep room
+// for interactive ones. Waiting interactive chunks also take free engines first.
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::metrics::METRICS;
+use crate::openai_error;
+use crate::pool::EnginePool;
+
+/// Request header choosing the priority class; it can lower a key's class but not raise it
+pub const PRIORITY_HEADER: &str = "x-priority";
+
+/// Priority classes, most urgent first
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Someone is waiting for the audio
+    #[default]
+    Interactive,
+    /// Bulk or background work that can wait
+    Batch,
+}
+
+impl Priority {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "interactive" => Some(Priority::Interactive),
+            "batch" => Some(Priority::Batch),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Batch => "batch",
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueInner {
+    /// Admitted requests
+    depth: usize,
+    /// Estimated cost of the admitted requests
+    cost: usize,
+}
+
+pub struct WorkQueue {
+    max_depth: usize,
+    engines: Arc<EnginePool>,
+    inner: Mutex<QueueInner>,
+}
+
+/// Why a request was not admitted
+pub struct QueueFull {
+    retry_after: Duration,
+    priority: Priority,
+}
+
+/// A request's place in the queue, released when dropped
+pub struct Admission {
+    queue: Arc<WorkQueue>,
+    cost: usize,
+}
+
+impl WorkQueue {
+    pub fn new(max_depth: usize, engines: Arc<EnginePool>) -> Self {
+        WorkQueue { max_depth, engines, inner: Mutex::new(QueueInner::default()) }
+    }
+
+    /// Estimated cost of synthesizing `input`, in characters
+    pub fn cost(input: &str) -> usize {
+        input.chars().count()
+    }
+
+    /// Admit a request of estimated `cost`, or tell it when to come back
+    pub fn admit(self: &Arc<Self>, priority: Priority, cost: usize) -> Result<Admission, QueueFull> {
+        let limit = match priority {
+            Priority::Interactive => self.max_depth,
+            Priority::Batch => (self.max_depth * 3 / 4).max(1),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.depth >= limit {
+            // Time for the engines to work through what is already queued
+            let secs = inner.cost as f64 * self.engines.secs_per_char() / self.engines.size() as f64;
+            METRICS.queue_rejections.with_label_values(&[priority.as_str()]).inc();
+            return Err(QueueFull { retry_after: Duration::from_secs_f64(secs), priority });
+        }
+        inner.depth += 1;
+        inner.cost += cost;
+        METRICS.queue_depth.set(inner.depth as i64);
+        Ok(Admission { queue: self.clone(), cost })
+    }
+}
+
+impl QueueFull {
+    /// Whole seconds to wait before retrying, at least one
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+
+    pub fn message(&self) -> String {
+        format!(
+            "The server is busy ({} queue full). Please try again in {}s.",
+            self.priority.as_str(),
+            self.retry_after_secs()
+        )
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        let mut inner = self.queue.inner.lock().unwrap();
+        inner.depth -= 1;
+        inner.cost -= self.cost;
+        METRICS.queue_depth.set(inner.depth as i64);
+    }
+}
+
+/// 503 with `Retry-After`, so clients back off instead of piling up
+impl IntoResponse for QueueFull {
+    fn into_response(self) -> Response {
+        let mut response = openai_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &self.message(),
+            "server_error",
+            None,
+            Some("queue_full"),
+        );
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(self.retry_after_secs()));
+        response
+    }
+}
@@ -0,0 +1,256 @@
+// ============================================================================
+// Rate Limiting - Requests and input characters per minute, per client
+// ============================================================================
+//
+// Clients are identified by their API key when authenticated, otherwise by IP address.
+// Each client gets two token buckets refilled continuously over a minute, so short
+// bursts up to the per-minute limit are allowed.
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::ApiKeyId;
+use crate::openai_error;
+
+/// Largest request body buffered to count input characters, matching axum's `Json` limit
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Idle clients are forgotten once this many are tracked
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RateLimits {
+    pub requests_per_minute: Option<u32>,
+    pub chars_per_minute: Option<u32>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(capacity: u32, now: Instant) -> Self {
+        Bucket { tokens: capacity as f64, updated: now }
+    }
+
+    fn refill(&mut self, capacity: u32, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * capacity as f64 / 60.0).min(capacity as f64);
+        self.updated = now;
+    }
+
+    /// Time until `cost` tokens are available
+    fn wait_for(&self, cost: f64, capacity: u32) -> Duration {
+        if self.tokens >= cost {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((cost - self.tokens) * 60.0 / capacity as f64)
+    }
+
+    fn is_full(&self, capacity: u32) -> bool {
+        self.tokens >= capacity as f64
+    }
+}
+
+struct ClientBuckets {
+    requests: Bucket,
+    chars: Bucket,
+}
+
+/// Why a request was limited
+pub struct RateLimited {
+    /// `None` when the request can never fit in the limit
+    retry_after: Option<Duration>,
+    message: String,
+    kind: &'static str,
+}
+
+pub struct RateLimiter {
+    limits: RateLimits,
+    clients: Mutex<HashMap<String, ClientBuckets>>,
+}
+
+impl RateLimiter {
+    /// `None` when no limit is configured
+    pub fn new(limits: RateLimits) -> Option<Self> {
+        if limits.requests_per_minute.is_none() && limits.chars_per_minute.is_none() {
+            return None;
+        }
+        Some(RateLimiter {
+            limits,
+            clients: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Charge `requests` and `chars` to `client`; nothing is charged when either limit is hit
+    pub fn check(&self, client: &str, requests: u32, chars: usize) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let rpm = self.limits.requests_per_minute.unwrap_or(u32::MAX);
+        let cpm = self.limits.chars_per_minute.unwrap_or(u32::MAX);
+
+        if let Some(limit) = self.limits.chars_per_minute {
+            if chars > limit as usize {
+                return Err(RateLimited {
+                    retry_after: None,
+                    message: format!(
+                        "Request too large: input of {} characters exceeds the limit of {} characters per minute",
+                        chars, limit
+                    ),
+                    kind: "tokens",
+                });
+            }
+        }
+
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED_CLIENTS {
+            for buckets in clients.values_mut() {
+                buckets.requests.refill(rpm, now);
+                buckets.chars.refill(cpm, now);
+            }
+            clients.retain(|_, b| !(b.requests.is_full(rpm) && b.chars.is_full(cpm)));
+        }
+
+        let buckets = clients.entry(client.to_string()).or_insert_with(|| ClientBuckets {
+            requests: Bucket::full(rpm, now),
+            chars: Bucket::full(cpm, now),
+        });
+        buckets.requests.refill(rpm, now);
+        buckets.chars.refill(cpm, now);
+
+        if let Some(limit) = self.limits.requests_per_minute {
+            let wait = buckets.requests.wait_for(requests as f64, limit);
+            if !wait.is_zero() {
+                return Err(RateLimited {
+                    retry_after: Some(wait),
+                    message: format!(
+                        "Rate limit reached on requests per minute: limit {}. Please try again in {}s.",
+                        limit,
+                        retry_after_secs(wait)
+                    ),
+                    kind: "requests",
+                });
+            }
+        }
+        if let Some(limit) = self.limits.chars_per_minute {
+            let wait = buckets.chars.wait_for(chars as f64, limit);
+            if !wait.is_zero() {
+                return Err(RateLimited {
+                    retry_after: Some(wait),
+                    message: format!(
+                        "Rate limit reached on input characters per minute: limit {}, requested {}. Please try again in {}s.",
+                        limit,
+                        chars,
+                        retry_after_secs(wait)
+                    ),
+                    kind: "tokens",
+                });
+            }
+        }
+
+        buckets.requests.tokens -= requests as f64;
+        buckets.chars.tokens -= chars as f64;
+        Ok(())
+    }
+}
+
+impl RateLimited {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// 429 with `Retry-After` and OpenAI's `rate_limit_exceeded` body
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        let mut response = openai_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            &self.message,
+            self.kind,
+            None,
+            Some("rate_limit_exceeded"),
+        );
+        if let Some(wait) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs(wait)));
+        }
+        response
+    }
+}
+
+fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// Limiter and client identity, handed to handlers that charge usage later (the WebSocket)
+#[derive(Clone)]
+pub struct ClientLimit {
+    limiter: Arc<RateLimiter>,
+    client: String,
+}
+
+impl ClientLimit {
+    pub fn check_chars(&self, chars: usize) -> Result<(), RateLimited> {
+        self.limiter.check(&self.client, 0, chars)
+    }
+}
+
+#[derive(Deserialize)]
+struct SpeechInput {
+    input: Option<String>,
+}
+
+/// Middleware charging one request plus the JSON body's `input` characters to the client
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = match request.extensions().get::<ApiKeyId>() {
+        Some(ApiKeyId(name)) => format!("key:{}", name),
+        None => format!("ip:{}", addr.ip()),
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let (chars, body) = if limiter.limits.chars_per_minute.is_some() {
+        let bytes = match to_bytes(body, MAX_BODY_BYTES).await {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return openai_error(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "Request body is too large",
+                    "invalid_request_error",
+                    None,
+                    None,
+                )
+            }
+        };
+        // Malformed bodies are left for the handler to reject
+        let chars = serde_json::from_slice::<SpeechInput>(&bytes)
+            .ok()
+            .and_then(|body| body.input)
+            .map_or(0, |input| input.chars().count());
+        (chars, Body::from(bytes))
+    } else {
+        (0, body)
+    };
+
+    if let Err(limited) = limiter.check(&client, 1, chars) {
+        return limited.into_response();
+    }
+
+    parts.extensions.insert(ClientLimit { limiter, client });
+    next.run(Request::from_parts(parts, body)).await
+}
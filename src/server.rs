@@ -1,43 +1,117 @@
 use axum::{
-    extract::{State, Json},
+    body::{Body, Bytes},
+    extract::{Extension, State, Json},
     http::{StatusCode, HeaderMap, header},
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    middleware,
+    response::{IntoResponse, Response, sse::{Event, KeepAlive, Sse}},
+    routing::{get, patch, post, put},
     Router,
 };
-use serde::{Deserialize};
-use std::{collections::HashMap, net::SocketAddr, sync::{Arc, Mutex}, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{collections::{HashMap, VecDeque}, convert::Infallible, fmt, net::SocketAddr, sync::{Arc, RwLock}, path::PathBuf};
 use tracing::{info, error};
 use anyhow::Result;
-use std::process::Stdio;
-use tokio::process::Command;
-use tokio::io::AsyncWriteExt;
 use sha2::{Sha256, Digest};
-use std::time::{SystemTime, Duration};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use clap::Parser;
 
+mod audio;
+mod auth;
+mod batch;
+mod cache;
+mod config;
+mod encoders;
+mod flight;
 mod helper;
-use helper::{TextToSpeech, Style, load_text_to_speech, load_voice_style};
+mod metrics;
+mod models;
+mod pool;
+mod queue;
+mod ratelimit;
+mod voices;
+mod ws;
+use auth::{ApiKeyId, ApiKeys, Scope, ScopeGuard};
+use batch::{Batcher, ChunkJob};
+use cache::{sentence_key, AudioCache, CacheLimits, EntryKey, SentenceCache};
+use audio::{PcmChunk, EncodedChunk, convert_audio, determine_content_type};
+use config::ServerConfig;
+use flight::Flights;
+use helper::{CancelToken, Style, chunk_seed, chunk_text_for_lang, default_seed, load_voice_style, preprocess_text};
+use metrics::METRICS;
+use models::ModelInfo;
+use pool::EnginePool;
+use queue::{Priority, WorkQueue, PRIORITY_HEADER};
+use ratelimit::{RateLimiter, RateLimits};
+use voices::VoiceRegistry;
+
+/// Silence inserted between consecutive chunks of one input segment
+const CHUNK_SILENCE_SECS: f32 = 0.3;
+
+/// Response header echoing the seed the audio was generated with
+const SEED_HEADER: &str = "x-seed";
+
+/// Request header overriding `--request-timeout-secs`, in seconds
+const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
 
 // ============================================================================
 // Configuration & State
 // ============================================================================
 
 struct AppState {
-    tts: Arc<Mutex<TextToSpeech>>,
-    voice_styles: HashMap<String, Style>,
-    cache_dir: PathBuf,
+    engines: Arc<EnginePool>,
+    /// Cross-request batching; `None` sends every chunk to an engine on its own
+    batcher: Option<Batcher>,
+    voices: RwLock<VoiceRegistry>,
+    voice_style_dir: PathBuf,
+    default_voice: Option<String>,
+    default_total_step: usize,
+    /// Aliases, per-voice defaults and limits from `--config`
+    config: ServerConfig,
+    models: Vec<ModelInfo>,
+    cache: Arc<AudioCache>,
+    /// Synthesized chunks, shared with the engines' `call`
+    sentences: Option<Arc<SentenceCache>>,
+    /// Syntheses in progress, joined by identical requests
+    flights: Arc<Flights>,
+    /// Admission of requests that need synthesis
+    queue: Arc<WorkQueue>,
+    /// Time allowed per speech request unless it sets its own
+    request_timeout: Option<Duration>,
 }
 
 #[derive(Deserialize, Debug)]
 struct CreateSpeechRequest {
     model: Option<String>,
     input: String,
-    voice: String,
+    voice: Option<String>, // a name, or a mix such as "Sarah:0.7+Lily:0.3"
+    voice_mix: Option<HashMap<String, f32>>,
     response_format: Option<String>, // mp3, opus, aac, flac, wav, pcm
     speed: Option<f32>,
     // Supertonic specific fields
     total_step: Option<usize>,
     lang: Option<String>,
+    stream_format: Option<String>, // audio, sse
+    stream: Option<bool>, // chunked streaming, defaults to true
+    seed: Option<u64>, // seeds the initial noise; derived from the request when absent
+}
+
+/// Usage reported in the final `speech.audio.done` SSE event.
+/// There is no tokenizer, so input "tokens" are counted as characters.
+#[derive(Serialize, Debug)]
+struct SpeechUsage {
+    input_tokens: usize,
+    output_tokens: usize,
+    total_tokens: usize,
+}
+
+impl SpeechUsage {
+    fn for_input(input: &str) -> Self {
+        let input_tokens = input.chars().count();
+        SpeechUsage { input_tokens, output_tokens: 0, total_tokens: input_tokens }
+    }
 }
 
 // ============================================================================
@@ -46,28 +120,49 @@ struct CreateSpeechRequest {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = config::Args::parse();
+
     // Initialize logging
-    tracing_subscriber::fmt::init();
+    args.init_logging();
 
     info!("Initializing Supertonic OpenAI TTS Server...");
 
+    let mut server_config = match args.config {
+        Some(ref path) => {
+            let server_config = ServerConfig::load(path)?;
+            info!("Loaded config from {}", path.display());
+            server_config
+        }
+        None => ServerConfig::default(),
+    };
+
+    audio::init_ffmpeg(&args.ffmpeg_path);
+    server_config.settle_default_format()?;
+
     // Load TTS
-    let onnx_dir = "assets/onnx";
-    let tts = load_text_to_speech(onnx_dir, false)?;
-    info!("Loaded TTS models from {}", onnx_dir);
+    let onnx_dir = args.onnx_dir.to_string_lossy();
+    let sentences = (args.sentence_cache_bytes > 0).then(|| Arc::new(SentenceCache::new(args.sentence_cache_bytes as usize)));
+    let engines = Arc::new(EnginePool::load(&onnx_dir, args.engines as usize, args.chunk_batch_size as usize, sentences.clone())?);
+    info!("Loaded {} TTS engine(s) from {}", engines.size(), onnx_dir);
+    let queue = Arc::new(WorkQueue::new(args.queue_max_depth as usize, engines.clone()));
+    let models = models::loaded_models(engines.config());
+    let batcher = (args.batch_window_ms > 0).then(|| {
+        info!("Batching chunks within {} ms, up to {} per batch", args.batch_window_ms, args.max_batch_size);
+        Batcher::start(engines.clone(), Duration::from_millis(args.batch_window_ms), args.max_batch_size as usize)
+    });
 
     // Load Voice Styles
-    let voice_style_dir = "assets/voice_styles";
-    let mut voice_styles = HashMap::new();
+    let voice_style_dir = args.voice_style_dir.clone();
+    let mut voices = VoiceRegistry::default();
     
-    // Default mapping for OpenAI voice names
+    // Default mapping for OpenAI voice names, used when the config file declares no aliases
     let openai_mapping = vec![
         ("Alex", "M1"), ("James", "M2"), ("Robert", "M3"), ("Sam", "M4"), ("Daniel", "M5"),
         ("Sarah", "F1"), ("Lily", "F2"), ("Jessica", "F3"), ("Olivia", "F4"), ("Emily", "F5"),
     ];
 
     // Load all JSON files in voice_style_dir
-    let paths: Vec<PathBuf> = std::fs::read_dir(voice_style_dir)?
+    let paths: Vec<PathBuf> = std::fs::read_dir(&voice_style_dir)?
         .filter_map(|entry| entry.ok())
         .map(|entry| entry.path())
         .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
@@ -80,83 +175,317 @@ async fn main() -> Result<()> {
         match load_voice_style(&[path_str], false) {
             Ok(style) => {
                 info!("Loaded voice style: {}", file_stem);
-                voice_styles.insert(file_stem.clone(), style);
+                voices.insert_style(file_stem.clone(), style);
             }
             Err(e) => error!("Failed to load voice style {}: {}", file_stem, e),
         }
     }
 
-    // Apply OpenAI mappings
-    for (openai_name, target_style) in &openai_mapping {
-        if let Some(style) = voice_styles.get(*target_style) {
-            voice_styles.insert(openai_name.to_string(), style.clone());
-            info!("Mapped OpenAI voice '{}' to style '{}'", openai_name, target_style);
+    match server_config.aliases() {
+        Some(aliases) => {
+            // Declared aliases must all resolve; a typo would otherwise silently drop a voice
+            for (alias, target_style) in aliases {
+                if voices.contains(alias) && !voices.is_alias(alias) {
+                    anyhow::bail!("Alias '{}' would shadow the voice style of the same name", alias);
+                }
+                if !voices.add_alias(alias.clone(), target_style.clone()) {
+                    anyhow::bail!("Alias '{}' points to '{}', which is not a loaded voice style", alias, target_style);
+                }
+                info!("Mapped voice '{}' to style '{}'", alias, target_style);
+            }
+        }
+        None => {
+            // Apply OpenAI mappings
+            for (openai_name, target_style) in &openai_mapping {
+                if voices.add_alias(openai_name.to_string(), target_style.to_string()) {
+                    info!("Mapped OpenAI voice '{}' to style '{}'", openai_name, target_style);
+                }
+            }
         }
     }
 
-    // Create cache directory
-    let cache_dir = PathBuf::from("cache");
-    std::fs::create_dir_all(&cache_dir)?;
-    
-    // Start cache pruning task
-    let cache_dir_clone = cache_dir.clone();
-    tokio::spawn(async move {
-        prune_cache_task(cache_dir_clone).await;
-    });
+    // Load API keys; authentication stays off when none are configured
+    let mut api_keys = ApiKeys::default();
+    if let Some(ref path) = args.api_keys_file {
+        api_keys.add_file(path)?;
+    }
+    for entry in args.api_keys.iter().filter(|entry| !entry.trim().is_empty()) {
+        api_keys.add_entry(entry)?;
+    }
+    if let Some(ref token) = args.admin_token.as_ref().filter(|t| !t.is_empty()) {
+        api_keys.add_entry(&format!("{} admin admin-token", token))?;
+    }
+    if api_keys.is_enabled() {
+        info!("API key authentication enabled with {} key(s)", api_keys.len());
+    } else {
+        info!("No API keys configured; synthesis is open and admin endpoints are disabled");
+    }
+
+    server_config.validate(&voices, &api_keys)?;
+    if args.default_total_step as usize > server_config.limits.max_total_step {
+        anyhow::bail!("Default total_step {} exceeds limits.max_total_step", args.default_total_step);
+    }
+
+    if let Some(ref voice) = args.default_voice {
+        if !voices.contains(voice) {
+            anyhow::bail!("Default voice '{}' is not a loaded voice", voice);
+        }
+    }
+
+    let api_keys = Arc::new(api_keys);
+    let guard = |scope| middleware::from_fn_with_state(ScopeGuard::new(api_keys.clone(), scope), auth::authorize);
+
+    // Open the cache and start pruning it
+    let cache = Arc::new(AudioCache::open(args.cache_dir.clone(), CacheLimits {
+        max_age_secs: args.cache_max_age_secs,
+        max_bytes: args.cache_max_bytes,
+        memory_bytes: args.memory_cache_bytes,
+    })?);
+    let prune_interval = Duration::from_secs(args.cache_prune_interval_secs);
+    tokio::spawn(cache::maintenance_task(cache.clone(), prune_interval));
 
     let app_state = Arc::new(AppState {
-        tts: Arc::new(Mutex::new(tts)),
-        voice_styles,
-        cache_dir,
+        engines,
+        batcher,
+        voices: RwLock::new(voices),
+        voice_style_dir,
+        default_voice: args.default_voice.clone(),
+        default_total_step: args.default_total_step as usize,
+        config: server_config,
+        models,
+        cache,
+        sentences,
+        flights: Arc::new(Flights::default()),
+        queue,
+        request_timeout: (args.request_timeout_secs > 0).then(|| Duration::from_secs(args.request_timeout_secs)),
     });
 
-    let app = Router::new()
+    // Rate limits run after authentication so authenticated clients are limited per key
+    let rate_limiter = RateLimiter::new(RateLimits {
+        requests_per_minute: args.rate_limit_rpm,
+        chars_per_minute: args.rate_limit_chars_per_min,
+    });
+    let mut synthesize_routes = Router::new()
         .route("/v1/audio/speech", post(create_speech))
+        .route("/v1/audio/speech/ws", get(ws::speech_ws));
+    if let Some(rate_limiter) = rate_limiter {
+        info!("Rate limits: {:?} requests/min, {:?} input characters/min", args.rate_limit_rpm, args.rate_limit_chars_per_min);
+        synthesize_routes = synthesize_routes
+            .route_layer(middleware::from_fn_with_state(Arc::new(rate_limiter), ratelimit::limit));
+    }
+    let synthesize_routes = synthesize_routes.route_layer(guard(Some(Scope::Synthesize)));
+
+    let admin_routes = Router::new()
+        .route("/v1/audio/voices/blend", post(voices::register_blend))
+        .route("/v1/audio/voices/:name", put(voices::upload_voice).delete(voices::delete_voice))
+        .route("/v1/audio/voices/:name/rename", post(voices::rename_voice))
+        .route("/v1/cache", get(cache::cache_stats))
+        .route("/v1/cache/entries", get(cache::list_entries).delete(cache::purge_entries))
+        .route("/v1/cache/limits", patch(cache::update_limits))
+        .route_layer(guard(Some(Scope::Admin)));
+
+    // Listings are readable with a key of any scope
+    let read_routes = Router::new()
+        .route("/v1/audio/voices", get(voices::list_voices))
+        .route("/v1/models", get(models::list_models))
+        .route("/v1/models/:model", get(models::retrieve_model))
+        .route_layer(guard(None));
+
+    let cache = app_state.cache.clone();
+    let app = Router::new()
+        .merge(synthesize_routes)
+        .merge(admin_routes)
+        .merge(read_routes)
         .route("/health", get(health_check))
+        .route("/metrics", get(metrics::metrics_handler))
         .with_state(app_state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
+    let addr = SocketAddr::new(args.host, args.port);
     info!("Listening on {}", addr);
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    // Keep the hits and access times recorded since the last periodic flush
+    cache.flush_index().await;
+    info!("Shut down");
 
     Ok(())
 }
 
+/// Resolves on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutting down");
+}
+
 async fn health_check() -> impl IntoResponse {
     StatusCode::OK
 }
 
+/// Error response in OpenAI's `{"error": {...}}` shape
+fn openai_error(
+    status: StatusCode,
+    message: &str,
+    error_type: &str,
+    param: Option<&str>,
+    code: Option<&str>,
+) -> Response {
+    let body = serde_json::json!({
+        "error": {
+            "message": message,
+            "type": error_type,
+            "param": param,
+            "code": code,
+        }
+    });
+    (status, Json(body)).into_response()
+}
+
 async fn create_speech(
     State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKeyId>>,
+    headers: HeaderMap,
     Json(payload): Json<CreateSpeechRequest>,
 ) -> Response {
+    // Label values are limited to known voices and formats to bound the metric's cardinality
+    let voice_label = match (payload.voice_mix.as_ref(), payload.voice.as_deref().or(state.default_voice.as_deref())) {
+        (None, Some(voice)) if state.voices.read().unwrap().contains(voice) => voice.to_string(),
+        (None, Some(voice)) if voice.contains(['+', ':']) => "blend".to_string(),
+        (Some(_), _) => "blend".to_string(),
+        _ => "unknown".to_string(),
+    };
+    let format_label = match payload.response_format.as_deref() {
+        None => state.config.default_format(),
+        Some(format) if audio::SUPPORTED_FORMATS.contains(&format) => format,
+        Some(_) => "unknown",
+    }
+    .to_string();
+
+    let key = key.map(|Extension(ApiKeyId(name))| name);
+    let response = speech_response(state, payload, key.as_deref(), &headers).await;
+    METRICS
+        .requests
+        .with_label_values(&[&voice_label, &format_label, response.status().as_str()])
+        .inc();
+    response
+}
+
+async fn speech_response(state: Arc<AppState>, payload: CreateSpeechRequest, key: Option<&str>, headers: &HeaderMap) -> Response {
     // Validate input
     if payload.input.is_empty() {
         return (StatusCode::BAD_REQUEST, "Input text cannot be empty").into_response();
     }
+
+    // The header may lower the key's priority class, never raise it
+    let key_priority = state.config.priority_for(key);
+    let priority = match headers.get(PRIORITY_HEADER) {
+        None => key_priority,
+        Some(value) => match value.to_str().ok().and_then(Priority::parse) {
+            Some(requested) => requested.max(key_priority),
+            None => return (StatusCode::BAD_REQUEST, "Invalid X-Priority header. Supported: interactive, batch").into_response(),
+        },
+    };
+
+    // The deadline covers queueing as well as synthesis
+    let limits = &state.config.limits;
+    let max_timeout = Duration::from_secs(limits.max_request_timeout_secs);
+    let timeout = match headers.get(REQUEST_TIMEOUT_HEADER) {
+        None => state.request_timeout,
+        Some(value) => match value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .filter(|timeout| !timeout.is_zero() && *timeout <= max_timeout)
+        {
+            Some(timeout) => Some(timeout),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid X-Request-Timeout header: expected a positive number of seconds up to {}", limits.max_request_timeout_secs),
+                )
+                    .into_response()
+            }
+        },
+    };
+    // A default too far in the future to represent means no deadline
+    let deadline = timeout.and_then(|timeout| tokio::time::Instant::now().checked_add(timeout));
+    if let Some(max_chars) = limits.max_input_chars {
+        if payload.input.chars().count() > max_chars {
+            return (StatusCode::BAD_REQUEST, format!("Input text exceeds the limit of {} characters", max_chars)).into_response();
+        }
+    }
     
     // Model check
     if let Some(ref m) = payload.model {
-        if m != "supertonic-2" && m != "tts-1" {
-             info!("Received request for model '{}', using supertonic-2", m);
+        if !state.models.iter().any(|info| &info.id == m) {
+            return models::model_not_found(m);
         }
     }
 
-    let voice_name = payload.voice.clone();
-    if !state.voice_styles.contains_key(&voice_name) {
-         return (StatusCode::BAD_REQUEST, format!("Unsupported voice: {}", voice_name)).into_response();
-    }
+    let mix = match (payload.voice_mix.as_ref(), payload.voice.as_deref()) {
+        (Some(voice_mix), _) => voices::mix_from_map(voice_mix),
+        (None, Some(voice)) => voices::parse_voice_spec(voice),
+        (None, None) => match state.default_voice {
+            Some(ref voice) => Ok(vec![(voice.clone(), 1.0)]),
+            None => Err("voice is required".to_string()),
+        },
+    };
+    let resolved = mix.and_then(|mix| {
+        let voices = state.voices.read().unwrap();
+        let (style, voice_name) = voices.resolve(&mix)?;
+        // Per-voice defaults and languages only apply to a single named voice, not a blend
+        let voice_id = match mix.as_slice() {
+            [(name, _)] => Some((name.clone(), voices.style_id_of(name).to_string())),
+            _ => None,
+        };
+        Ok((style, voice_name, voice_id))
+    });
+    let (style, voice_name, voice_id) = match resolved {
+        Ok(resolved) => resolved,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let voice_id = voice_id.as_ref().map(|(name, style_id)| (name.as_str(), style_id.as_str()));
+    let voice_defaults = voice_id.and_then(|(name, style_id)| state.config.voice_defaults(name, style_id));
     
     // Validate total_step
-    let total_step = payload.total_step.unwrap_or(5);
-    if total_step < 1 || total_step > 10 {
-        return (StatusCode::BAD_REQUEST, "total_step must be between 1 and 10").into_response();
+    let total_step = payload.total_step
+        .or(voice_defaults.and_then(|d| d.total_step))
+        .unwrap_or(state.default_total_step);
+    if total_step < 1 || total_step > limits.max_total_step {
+        return (StatusCode::BAD_REQUEST, format!("total_step must be between 1 and {}", limits.max_total_step)).into_response();
     }
     
     // Parse Languages
-    let lang_str = payload.lang.clone().unwrap_or_else(|| "en".to_string());
-    let valid_langs = ["en", "ko", "es", "pt", "fr"];
+    let lang_str = payload.lang.clone()
+        .unwrap_or_else(|| state.config.default_lang_for(voice_id));
+    let valid_langs = state.config.languages_for(voice_id);
     let langs: Vec<String> = lang_str.split(',')
         .map(|s| s.trim().to_string())
         .collect();
@@ -194,62 +523,140 @@ async fn create_speech(
         return (StatusCode::BAD_REQUEST, format!("Mismatch: Input has {} segments (split by '|'), but {} languages provided. They must match or provide single language.", input_segments.len(), langs.len())).into_response();
     }
 
-    let speed = payload.speed.unwrap_or(1.0);
-    let format = payload.response_format.as_deref().unwrap_or("mp3");
+    let speed = payload.speed
+        .or(voice_defaults.and_then(|d| d.speed))
+        .unwrap_or(1.0);
+    if !(limits.min_speed..=limits.max_speed).contains(&speed) {
+        return (StatusCode::BAD_REQUEST, format!("speed must be between {} and {}", limits.min_speed, limits.max_speed)).into_response();
+    }
+    let format = payload.response_format.as_deref().unwrap_or(state.config.default_format());
+    if let Err(message) = audio::check_format(format) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let sse = match payload.stream_format.as_deref() {
+        None | Some("audio") => false,
+        Some("sse") => true,
+        Some(other) => return (StatusCode::BAD_REQUEST, format!("Invalid stream_format: {}. Supported: audio, sse", other)).into_response(),
+    };
     
-    // Check cache
-    let cache_key = format!("{}:{}:{}:{:.2}:{}:{}", payload.input, voice_name, format, speed, total_step, lang_str);
-    let mut hasher = Sha256::new();
-    hasher.update(cache_key.as_bytes());
-    let hash = hex::encode(hasher.finalize());
-    let cache_path = state.cache_dir.join(format!("{}.{}", hash, format));
-
-    if cache_path.exists() {
+    // Without an explicit seed, identical requests still produce identical audio in every format
+    let seed = payload.seed.unwrap_or_else(|| default_seed(&voice_name, speed, total_step, &lang_str));
+
+    // Synthesized PCM is cached once for every format; the model and style fingerprints
+    // keep replaced files from serving stale audio
+    let pcm_key = format!(
+        "{}:{}:{}:{}:{:.2}:{}:{}:{}",
+        state.engines.model_fingerprint, style.fingerprint(), payload.input, voice_name, speed, total_step, lang_str, seed
+    );
+    let pcm_hash = hex::encode(Sha256::digest(pcm_key.as_bytes()));
+    let hash = hex::encode(Sha256::digest(format!("{}:{}", pcm_hash, format).as_bytes()));
+    let entry_key = EntryKey {
+        input: payload.input.clone(),
+        voice: voice_name.clone(),
+        format: format.to_string(),
+        lang: lang_str.clone(),
+        speed,
+        total_step,
+        seed,
+    };
+
+    if let Some(bytes) = state.cache.get(&hash, format, &pcm_hash).await {
         info!("Cache hit for {}", hash);
-        match tokio::fs::read(&cache_path).await {
-            Ok(bytes) => {
-                let mut headers = HeaderMap::new();
-                headers.insert(header::CONTENT_TYPE, determine_content_type(format).parse().unwrap());
-                return (headers, bytes).into_response();
-            }
-            Err(e) => error!("Failed to read cache: {}", e),
+        METRICS.cache_hits.inc();
+        if sse {
+            return with_seed(sse_from_audio(&bytes, SpeechUsage::for_input(&payload.input)), seed);
         }
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, determine_content_type(format).parse().unwrap());
+        return with_seed((headers, bytes).into_response(), seed);
     }
 
-    info!("Generating speech for voice '{}', speed {}, format '{}', steps {}", voice_name, speed, format, total_step);
-    
-    // Blocking call to TTS
-    let tts_arc = state.tts.clone();
-    let voice_name_clone = voice_name.clone();
-    
-    let state_clone = state.clone();
+    let sample_rate = state.engines.sample_rate;
 
-    let generation_result = tokio::task::spawn_blocking(move || {
-        let mut tts = tts_arc.lock().unwrap();
-        let style = state_clone.voice_styles.get(&voice_name_clone).unwrap();
-        
-        let mut all_wavs = Vec::new();
-        let mut total_dur = 0.0;
-        
-        // Process each segment
-        for (text, lang) in input_segments.iter().zip(aligned_langs.iter()) {
-             let (wav, dur) = tts.call(text, lang, style, total_step, speed, 0.3)?;
-             all_wavs.extend(wav);
-             total_dur += dur;
+    let wav_samples = if let Some(samples) = state.cache.get_pcm(&pcm_hash).await {
+        info!("Encoding {} from cached PCM {}", format, pcm_hash);
+        METRICS.cache_transcodes.inc();
+        samples
+    } else {
+        // Identical requests in flight share one synthesis, whose PCM is cached once it completes
+        let long_form = !sse && !payload.stream.unwrap_or(true) && state.engines.chunk_batch_size() > 1;
+        let pcm_rx = {
+            let synth_state = state.clone();
+            let cache_state = state.clone();
+            let hash = pcm_hash.clone();
+            let key = entry_key.clone();
+            let cost = WorkQueue::cost(&payload.input);
+            // Requests joining a synthesis in flight add no work, so only a new one is admitted
+            let joined = state.flights.join(
+                &pcm_hash,
+                || state.queue.admit(priority, cost),
+                move || {
+                    if long_form {
+                        spawn_long_form(synth_state, style, input_segments, aligned_langs, total_step, speed, seed, priority)
+                    } else {
+                        spawn_synthesis(synth_state, style, input_segments, aligned_langs, total_step, speed, seed, priority)
+                    }
+                },
+                move |samples| async move { cache_state.cache.put_pcm(&hash, key, &samples).await },
+            );
+            match joined {
+                Ok(pcm_rx) => pcm_rx,
+                Err(full) => return full.into_response(),
+            }
+        };
+        METRICS.cache_misses.inc();
+        info!("Generating speech for voice '{}', speed {}, format '{}', steps {}", voice_name, speed, format, total_step);
+
+        // Dropping `client` (the response going away) or passing the deadline leaves the synthesis
+        let (mut pcm_rx, client) = watch_request(pcm_rx, deadline);
+
+        if sse {
+            let response = sse_speech(pcm_rx, sample_rate, format.to_string(), SpeechUsage::for_input(&payload.input), client);
+            return with_seed(response, seed);
         }
-        
-        Ok::<_, anyhow::Error>((all_wavs, total_dur))
-    }).await;
 
-    let (wav_samples, _duration) = match generation_result {
-        Ok(Ok(res)) => res,
-        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("TTS Error: {}", e)).into_response(),
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Task Error: {}", e)).into_response(),
-    };
-    
-    let sample_rate = {
-        let tts = state.tts.lock().unwrap();
-        tts.sample_rate
+        if payload.stream.unwrap_or(true) {
+            // Wait for the first chunk so early failures still produce a proper error status
+            let first = match pcm_rx.recv().await {
+                Some(Ok(samples)) => samples,
+                Some(Err(e)) => return synthesis_error(e),
+                None => return (StatusCode::INTERNAL_SERVER_ERROR, "Task Error: synthesis ended unexpectedly").into_response(),
+            };
+
+            let (enc_tx, enc_rx) = mpsc::channel::<PcmChunk>(4);
+            let _ = enc_tx.try_send(Ok(first));
+            tokio::spawn(async move {
+                while let Some(chunk) = pcm_rx.recv().await {
+                    if enc_tx.send(chunk).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let encoded_rx = audio::encode_stream(enc_rx, sample_rate, format);
+            let body_rx = tee_to_cache(state.clone(), encoded_rx, hash, entry_key);
+            // The body holds `client`, so a disconnect cancels the synthesis without waiting for the next chunk
+            let body = ReceiverStream::new(body_rx).map(move |item| {
+                let _ = &client;
+                item
+            });
+
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, determine_content_type(format).parse().unwrap());
+            return with_seed((headers, Body::from_stream(body)).into_response(), seed);
+        }
+
+        // Collect the whole input before encoding; `client` is dropped with this handler
+        let _client = client;
+        let mut wav_samples = Vec::new();
+        while let Some(chunk) = pcm_rx.recv().await {
+            match chunk {
+                Ok(samples) => wav_samples.extend(samples),
+                Err(e) => return synthesis_error(e),
+            }
+        }
+        wav_samples
     };
 
     // Convert to requested format
@@ -258,143 +665,390 @@ async fn create_speech(
         Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Conversion Error: {}", e)).into_response(),
     };
 
-    // Save to cache
-    if let Err(e) = tokio::fs::write(&cache_path, &audio_bytes).await {
-        error!("Failed to write to cache: {}", e);
-    }
+    let audio_bytes = Bytes::from(audio_bytes);
+    state.cache.put(&hash, entry_key, audio_bytes.clone()).await;
 
+    if sse {
+        return with_seed(sse_from_audio(&audio_bytes, SpeechUsage::for_input(&payload.input)), seed);
+    }
     let mut headers = HeaderMap::new();
     headers.insert(header::CONTENT_TYPE, determine_content_type(format).parse().unwrap());
-    (headers, audio_bytes).into_response()
+    with_seed((headers, audio_bytes).into_response(), seed)
 }
 
-fn determine_content_type(format: &str) -> String {
-    match format {
-        "mp3" => "audio/mpeg",
-        "opus" => "audio/opus",
-        "aac" => "audio/aac",
-        "flac" => "audio/flac",
-        "wav" => "audio/wav",
-        "pcm" => "audio/pcm", 
-        _ => "application/octet-stream",
+/// Error ending a request whose deadline passed before its audio was complete
+#[derive(Debug)]
+struct RequestTimedOut;
+
+impl fmt::Display for RequestTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request timed out before synthesis completed")
     }
-    .to_string()
 }
 
-async fn convert_audio(samples: &[f32], sample_rate: i32, format: &str) -> Result<Vec<u8>> {
-    if format == "pcm" {
-        let mut bytes = Vec::with_capacity(samples.len() * 2);
-        for &sample in samples {
-            let clamped = sample.max(-1.0).min(1.0);
-            let val = (clamped * 32767.0) as i16;
-            bytes.extend_from_slice(&val.to_le_bytes());
-        }
-        return Ok(bytes);
+impl std::error::Error for RequestTimedOut {}
+
+/// 504 for requests that ran out of time, 500 for synthesis failures
+fn synthesis_error(e: anyhow::Error) -> Response {
+    if e.is::<RequestTimedOut>() {
+        return (StatusCode::GATEWAY_TIMEOUT, e.to_string()).into_response();
     }
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("TTS Error: {}", e)).into_response()
+}
+
+/// Forward a request's chunks until its client goes away or its deadline passes.
+///
+/// The returned sender must live as long as the response is being delivered. Dropping it,
+/// like passing the deadline, drops `pcm_rx`, which cancels the synthesis once no other
+/// request shares it. A passed deadline ends the chunks with a [`RequestTimedOut`] error.
+fn watch_request(
+    mut pcm_rx: mpsc::Receiver<PcmChunk>,
+    deadline: Option<tokio::time::Instant>,
+) -> (mpsc::Receiver<PcmChunk>, oneshot::Sender<()>) {
+    let (tx, rx) = mpsc::channel::<PcmChunk>(4);
+    let (client, mut client_gone) = oneshot::channel::<()>();
 
-    if format == "wav" {
-        let spec = hound::WavSpec {
-            channels: 1,
-            sample_rate: sample_rate as u32,
-            bits_per_sample: 16,
-            sample_format: hound::SampleFormat::Int,
+    tokio::spawn(async move {
+        let expired = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
         };
-        let mut cursor = std::io::Cursor::new(Vec::new());
-        let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
-        for &sample in samples {
-            let clamped = sample.max(-1.0).min(1.0);
-            let val = (clamped * 32767.0) as i16;
-            writer.write_sample(val)?;
+        tokio::pin!(expired);
+
+        loop {
+            tokio::select! {
+                chunk = pcm_rx.recv() => {
+                    let Some(chunk) = chunk else {
+                        return;
+                    };
+                    tokio::select! {
+                        sent = tx.send(chunk) => if sent.is_err() {
+                            return;
+                        },
+                        _ = &mut client_gone => return,
+                    }
+                }
+                _ = &mut expired => {
+                    info!("Request deadline passed, cancelling synthesis");
+                    METRICS.request_timeouts.inc();
+                    drop(pcm_rx);
+                    let _ = tx.send(Err(RequestTimedOut.into())).await;
+                    return;
+                }
+                _ = &mut client_gone => {
+                    info!("Client disconnected, cancelling synthesis");
+                    return;
+                }
+            }
         }
-        writer.finalize()?;
-        return Ok(cursor.into_inner());
-    }
+    });
 
-    let mut cmd = Command::new("ffmpeg");
-    cmd.args(&[
-        "-f", "s16le", 
-        "-ar", &sample_rate.to_string(),
-        "-ac", "1",
-        "-i", "pipe:0", 
-        "-f", format,   
-        "pipe:1"        
-    ]);
-    
-    cmd.stdin(Stdio::piped());
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::null());
+    (rx, client)
+}
 
-    let mut child = cmd.spawn()?;
+fn with_seed(mut response: Response, seed: u64) -> Response {
+    response.headers_mut().insert(SEED_HEADER, seed.into());
+    response
+}
 
-    let mut stdin = child.stdin.take().ok_or_else(|| anyhow::anyhow!("Failed to open stdin"))?;
-    
-    let mut pcm_bytes = Vec::with_capacity(samples.len() * 2);
-    for &sample in samples {
-        let clamped = sample.max(-1.0).min(1.0);
-        let val = (clamped * 32767.0) as i16;
-        pcm_bytes.extend_from_slice(&val.to_le_bytes());
+/// Synthesize one chunk, through the batcher when batching is enabled
+async fn synthesize_chunk(state: Arc<AppState>, job: ChunkJob) -> anyhow::Result<(Vec<f32>, f32)> {
+    if let Some(ref batcher) = state.batcher {
+        return batcher.synthesize(job).await;
     }
 
+    let result = tokio::task::spawn_blocking(move || {
+        // Cancelled work neither waits for an engine nor keeps one it was just given
+        if job.cancel.is_cancelled() {
+            return Err(anyhow::anyhow!("Synthesis cancelled"));
+        }
+        let mut tts = state.engines.checkout(job.priority);
+        if job.cancel.is_cancelled() {
+            return Err(anyhow::anyhow!("Synthesis cancelled"));
+        }
+        let started = Instant::now();
+        let (wav, dur) = tts.synthesize_chunk(&job.text, &job.lang, &job.style, job.total_step, job.speed, job.seed, &job.cancel)?;
+        state.engines.observe_synthesis(started.elapsed(), dur, job.text.chars().count());
+        Ok((wav, dur))
+    })
+    .await;
+    result.unwrap_or_else(|e| Err(anyhow::anyhow!("Task Error: {}", e)))
+}
+
+/// Synthesize chunk by chunk, sending audio in order as it is produced.
+///
+/// Up to one chunk per pool engine (or per batch slot, with batching) is in flight at
+/// once, so a long input spreads across idle engines. Chunks after the first one in a
+/// segment carry the inter-chunk silence as a prefix, so concatenating every item yields
+/// the same audio as `TextToSpeech::call`. Chunks are seeded and looked up in the sentence
+/// cache the way `call` does it. Synthesis stops at the first error or as soon as the
+/// receiver is dropped: chunks not started yet are aborted, and chunks already running
+/// are cancelled at their next denoising step.
+#[allow(clippy::too_many_arguments)]
+fn spawn_synthesis(
+    state: Arc<AppState>,
+    style: Arc<Style>,
+    input_segments: Vec<String>,
+    aligned_langs: Vec<String>,
+    total_step: usize,
+    speed: f32,
+    seed: u64,
+    priority: Priority,
+) -> mpsc::Receiver<PcmChunk> {
+    let (tx, rx) = mpsc::channel::<PcmChunk>(4);
+    let silence_len = (CHUNK_SILENCE_SECS * state.engines.sample_rate as f32) as usize;
+
+    let style_fingerprint = state.sentences.as_ref().map(|_| style.fingerprint());
+
+    // (chunk, lang, whether silence precedes it)
+    let chunks: Vec<(String, String, bool)> = input_segments
+        .iter()
+        .zip(aligned_langs.iter())
+        .flat_map(|(text, lang)| {
+            chunk_text_for_lang(text, lang)
+                .into_iter()
+                .enumerate()
+                .map(move |(i, chunk)| (chunk, lang.clone(), i > 0))
+        })
+        .collect();
+
+    let cancel = CancelToken::default();
+
     tokio::spawn(async move {
-        let _ = stdin.write_all(&pcm_bytes).await;
-    });
+        let _cancel_rest = cancel.cancel_on_drop();
+        let mut chunks = chunks.into_iter();
+        let mut in_flight = VecDeque::new();
 
-    let output = child.wait_with_output().await?;
-    
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("FFmpeg failed"));
-    }
+        let max_in_flight = match state.batcher {
+            Some(ref batcher) => state.engines.size() * batcher.max_batch_size(),
+            None => state.engines.size(),
+        };
+
+        loop {
+            while in_flight.len() < max_in_flight {
+                let Some((text, lang, leading_silence)) = chunks.next() else {
+                    break;
+                };
+                let state = state.clone();
+                let style = style.clone();
+                let style_fingerprint = style_fingerprint.clone();
+                let cancel = cancel.clone();
+                in_flight.push_back(tokio::spawn(async move {
+                    let normalized = preprocess_text(&text, &lang)?;
+                    let seed = chunk_seed(seed, &normalized);
+                    let key = style_fingerprint.map(|fp| sentence_key(&normalized, &fp, speed, total_step, seed));
+                    let sentences = state.sentences.clone();
+                    let wav = match sentences.as_ref().zip(key.as_deref()).and_then(|(cache, key)| cache.get(key)) {
+                        Some((wav, _)) => wav,
+                        None => {
+                            let job = ChunkJob { text, lang, style, total_step, speed, seed, priority, cancel };
+                            let (wav, dur) = synthesize_chunk(state, job).await?;
+                            if let Some((cache, key)) = sentences.as_ref().zip(key.as_deref()) {
+                                cache.insert(key, &wav, dur);
+                            }
+                            wav
+                        }
+                    };
+                    if !leading_silence {
+                        return Ok(wav);
+                    }
+                    let mut samples = vec![0.0f32; silence_len];
+                    samples.extend(wav);
+                    Ok(samples)
+                }));
+            }
+
+            let Some(handle) = in_flight.pop_front() else {
+                break;
+            };
+            let item = tokio::select! {
+                item = handle => match item {
+                    Ok(item) => item,
+                    Err(e) => Err(anyhow::anyhow!("Task Error: {}", e)),
+                },
+                _ = tx.closed() => break,
+            };
+            let failed = item.is_err();
+            if tx.send(item).await.is_err() || failed {
+                break;
+            }
+        }
 
-    Ok(output.stdout)
+        // Chunks still queued for an engine or a batch must not take one
+        cancel.cancel();
+        for handle in in_flight {
+            handle.abort();
+        }
+    });
+
+    rx
 }
 
-async fn prune_cache_task(cache_dir: PathBuf) {
-    let mut interval = tokio::time::interval(Duration::from_secs(3600)); // Every hour
-    loop {
-        interval.tick().await;
-        info!("Pruning cache...");
-        
-        let max_age = Duration::from_secs(86400 * 3); // 3 days
-        let max_size = 1024 * 1024 * 1024; // 1 GB
-        
-        let mut files = Vec::new();
-        let mut total_size = 0;
-
-        if let Ok(mut entries) = tokio::fs::read_dir(&cache_dir).await {
-            while let Ok(Some(entry)) = entries.next_entry().await {
-                if let Ok(metadata) = entry.metadata().await {
-                    if metadata.is_file() {
-                        total_size += metadata.len();
-                        files.push((entry.path(), metadata.modified().unwrap_or(SystemTime::now()), metadata.len()));
-                    }
+/// Long-form mode: one engine runs each segment's chunks through the models in batches
+/// with `TextToSpeech::call`, sending each segment's audio as one item
+#[allow(clippy::too_many_arguments)]
+fn spawn_long_form(
+    state: Arc<AppState>,
+    style: Arc<Style>,
+    input_segments: Vec<String>,
+    aligned_langs: Vec<String>,
+    total_step: usize,
+    speed: f32,
+    seed: u64,
+    priority: Priority,
+) -> mpsc::Receiver<PcmChunk> {
+    let (tx, rx) = mpsc::channel::<PcmChunk>(4);
+
+    tokio::spawn(async move {
+        let cancel = CancelToken::default();
+        let _cancel_rest = cancel.cancel_on_drop();
+        let segment_tx = tx.clone();
+        let handle = tokio::task::spawn_blocking(move || {
+            if cancel.is_cancelled() {
+                return;
+            }
+            let mut tts = state.engines.checkout(priority);
+            for (text, lang) in input_segments.iter().zip(aligned_langs.iter()) {
+                let started = Instant::now();
+                let result = tts.call(text, lang, &style, total_step, speed, CHUNK_SILENCE_SECS, seed, &cancel).map(|(wav, dur)| {
+                    state.engines.observe_synthesis(started.elapsed(), dur, text.chars().count());
+                    wav
+                });
+                let failed = result.is_err();
+                if segment_tx.blocking_send(result).is_err() || failed {
+                    return;
                 }
             }
+        });
+        // A dropped receiver cancels the running segment at its next chunk or denoising step
+        let result = tokio::select! {
+            result = handle => result,
+            _ = tx.closed() => return,
+        };
+        if let Err(e) = result {
+            let _ = tx.send(Err(anyhow::anyhow!("Task Error: {}", e))).await;
         }
+    });
 
-        files.sort_by(|a, b| a.1.cmp(&b.1));
+    rx
+}
 
-        let now = SystemTime::now();
+fn speech_event(value: serde_json::Value) -> Event {
+    Event::default().data(value.to_string())
+}
 
-        for (path, modified, size) in files {
-            let age = now.duration_since(modified).unwrap_or(Duration::from_secs(0));
-            let mut remove = false;
+/// Stream one `speech.audio.delta` event per synthesized chunk, then `speech.audio.done`.
+///
+/// Each delta carries its chunk encoded on its own, so every delta can be decoded
+/// independently. A synthesis or encoding failure ends the stream with an `error` event.
+fn sse_speech(
+    mut pcm_rx: mpsc::Receiver<PcmChunk>,
+    sample_rate: i32,
+    format: String,
+    usage: SpeechUsage,
+    client: oneshot::Sender<()>,
+) -> Response {
+    let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(16);
 
-            if age > max_age {
-                remove = true;
-            } else if total_size > max_size {
-                remove = true;
+    tokio::spawn(async move {
+        while let Some(chunk) = pcm_rx.recv().await {
+            let encoded = match chunk {
+                Ok(samples) => convert_audio(&samples, sample_rate, &format).await,
+                Err(e) => Err(e),
+            };
+            let event = match encoded {
+                Ok(audio) => speech_event(serde_json::json!({
+                    "type": "speech.audio.delta",
+                    "audio": BASE64.encode(audio),
+                })),
+                Err(e) => {
+                    error!("SSE synthesis error: {}", e);
+                    let event = speech_event(serde_json::json!({
+                        "type": "error",
+                        "error": { "message": format!("TTS Error: {}", e) },
+                    }));
+                    let _ = tx.send(Ok(event)).await;
+                    return;
+                }
+            };
+            if tx.send(Ok(event)).await.is_err() {
+                info!("Client disconnected, dropping SSE stream");
+                return;
             }
+        }
+
+        let done = speech_event(serde_json::json!({
+            "type": "speech.audio.done",
+            "usage": usage,
+        }));
+        let _ = tx.send(Ok(done)).await;
+    });
+
+    // The event stream holds `client`, so a disconnect cancels the synthesis right away
+    let events = ReceiverStream::new(rx).map(move |event| {
+        let _ = &client;
+        event
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Serve already encoded audio (e.g. a cache hit) as a single delta followed by `speech.audio.done`
+fn sse_from_audio(audio: &[u8], usage: SpeechUsage) -> Response {
+    let events = vec![
+        Ok::<_, Infallible>(speech_event(serde_json::json!({
+            "type": "speech.audio.delta",
+            "audio": BASE64.encode(audio),
+        }))),
+        Ok(speech_event(serde_json::json!({
+            "type": "speech.audio.done",
+            "usage": usage,
+        }))),
+    ];
+    Sse::new(tokio_stream::iter(events)).into_response()
+}
+
+/// Forward an encoded stream to the response body while buffering it for the cache.
+///
+/// The cache entry is only written when the whole stream was encoded and delivered.
+fn tee_to_cache(
+    state: Arc<AppState>,
+    mut encoded_rx: mpsc::Receiver<EncodedChunk>,
+    hash: String,
+    key: EntryKey,
+) -> mpsc::Receiver<EncodedChunk> {
+    let (tx, rx) = mpsc::channel::<EncodedChunk>(16);
 
-            if remove {
-                if let Ok(_) = tokio::fs::remove_file(&path).await {
-                    total_size -= size;
-                    info!("Pruned file: {:?}", path);
+    tokio::spawn(async move {
+        let mut audio_bytes = Vec::new();
+        while let Some(item) = encoded_rx.recv().await {
+            let failed = match &item {
+                Ok(bytes) => {
+                    audio_bytes.extend_from_slice(bytes);
+                    false
                 }
+                Err(e) => {
+                    error!("Streaming error: {}", e);
+                    true
+                }
+            };
+            if tx.send(item).await.is_err() {
+                info!("Client disconnected, dropping stream");
+                return;
             }
-
-            if total_size <= max_size && age <= max_age {
-                break;
+            if failed {
+                return;
             }
         }
-    }
+
+        if key.format == "wav" {
+            audio::finalize_wav_stream(&mut audio_bytes);
+        }
+        state.cache.put(&hash, key, Bytes::from(audio_bytes)).await;
+    });
+
+    rx
 }
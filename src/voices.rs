@@ -0,0 +1,542 @@
+// ============================================================================
+// Voices - Registry, catalogue and runtime registration of voice styles
+// ============================================================================
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+use crate::config::ServerConfig;
+use crate::helper::{load_voice_style, Style};
+use crate::{openai_error, AppState};
+
+/// Upper bound on cached blends; the cache is simply reset when it fills up
+const MAX_CACHED_BLENDS: usize = 256;
+
+/// Loaded voice styles plus the alias names pointing at them.
+///
+/// Aliases are resolved on lookup, so replacing a style also updates every alias of it.
+/// Blended styles are cached by their canonical spec and dropped whenever a style changes.
+#[derive(Default)]
+pub struct VoiceRegistry {
+    styles: HashMap<String, Arc<Style>>,
+    aliases: HashMap<String, String>,
+    blends: Mutex<HashMap<String, Arc<Style>>>,
+}
+
+impl VoiceRegistry {
+    pub fn insert_style(&mut self, style_id: String, style: Style) -> bool {
+        self.blends.get_mut().unwrap().clear();
+        self.styles.insert(style_id, Arc::new(style)).is_some()
+    }
+
+    /// Register `alias` for an existing style; returns false if the style is unknown
+    pub fn add_alias(&mut self, alias: String, style_id: String) -> bool {
+        if !self.styles.contains_key(&style_id) {
+            return false;
+        }
+        self.aliases.insert(alias, style_id);
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Style>> {
+        self.styles.get(self.style_id_of(name)).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.styles.contains_key(self.style_id_of(name))
+    }
+
+    pub fn is_alias(&self, name: &str) -> bool {
+        self.aliases.contains_key(name)
+    }
+
+    /// Resolve a voice name to its style ID, following aliases
+    pub fn style_id_of<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map(String::as_str).unwrap_or(name)
+    }
+
+    fn aliases_of(&self, style_id: &str) -> Vec<String> {
+        let mut aliases: Vec<String> = self
+            .aliases
+            .iter()
+            .filter(|(_, target)| *target == style_id)
+            .map(|(alias, _)| alias.clone())
+            .collect();
+        aliases.sort();
+        aliases
+    }
+
+    /// Any loaded style, used as the reference shape for uploads
+    fn reference_style(&self) -> Option<&Arc<Style>> {
+        self.styles.values().next()
+    }
+
+    fn remove_style(&mut self, style_id: &str) -> Option<Arc<Style>> {
+        self.blends.get_mut().unwrap().clear();
+        self.styles.remove(style_id)
+    }
+
+    fn rename_style(&mut self, from: &str, to: &str) -> bool {
+        let Some(style) = self.styles.remove(from) else {
+            return false;
+        };
+        self.blends.get_mut().unwrap().clear();
+        self.styles.insert(to.to_string(), style);
+        true
+    }
+
+    /// Resolve a voice mix to a style and the key identifying it in the audio cache.
+    ///
+    /// A single unweighted voice resolves to that voice under its own name. Mixes are keyed
+    /// by style IDs with normalized weights, so aliases and weight scaling share one entry.
+    pub fn resolve(&self, mix: &[(String, f32)]) -> Result<(Arc<Style>, String), String> {
+        if let [(name, _)] = mix {
+            let style = self.get(name).ok_or_else(|| format!("Unsupported voice: {}", name))?;
+            return Ok((style, name.clone()));
+        }
+
+        let mut weights: Vec<(String, f32)> = Vec::new();
+        for (name, weight) in mix {
+            if !self.contains(name) {
+                return Err(format!("Unsupported voice: {}", name));
+            }
+            let style_id = self.style_id_of(name).to_string();
+            match weights.iter_mut().find(|(id, _)| *id == style_id) {
+                Some((_, w)) => *w += weight,
+                None => weights.push((style_id, *weight)),
+            }
+        }
+        weights.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let total: f32 = weights.iter().map(|(_, w)| w).sum();
+        if total <= 0.0 {
+            return Err("Voice mix weights must sum to more than 0".to_string());
+        }
+        let key = weights
+            .iter()
+            .map(|(id, w)| format!("{}:{:.3}", id, w / total))
+            .collect::<Vec<_>>()
+            .join("+");
+
+        if let Some(style) = self.blends.lock().unwrap().get(&key) {
+            return Ok((style.clone(), key));
+        }
+
+        let styles: Vec<(Arc<Style>, f32)> = weights
+            .iter()
+            .map(|(id, w)| (self.styles[id].clone(), *w))
+            .collect();
+        let components: Vec<(&Style, f32)> = styles.iter().map(|(style, w)| (style.as_ref(), *w)).collect();
+        let style = Arc::new(Style::blend(&components).map_err(|e| e.to_string())?);
+
+        let mut blends = self.blends.lock().unwrap();
+        if blends.len() >= MAX_CACHED_BLENDS {
+            blends.clear();
+        }
+        blends.insert(key.clone(), style.clone());
+        Ok((style, key))
+    }
+}
+
+/// Parse a `voice` value: a name, or a weighted mix such as `Sarah:0.7+Lily:0.3`.
+///
+/// Weights default to 1, so `Sarah+Lily` is an even mix.
+pub fn parse_voice_spec(spec: &str) -> Result<Vec<(String, f32)>, String> {
+    let mut mix = Vec::new();
+    for part in spec.split('+') {
+        let (name, weight) = match part.split_once(':') {
+            Some((name, weight)) => {
+                let weight: f32 = weight
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid weight in voice mix: {}", part.trim()))?;
+                (name.trim(), weight)
+            }
+            None => (part.trim(), 1.0),
+        };
+        if name.is_empty() {
+            return Err(format!("Invalid voice: {}", spec));
+        }
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(format!("Voice mix weights must be non-negative numbers: {}", part.trim()));
+        }
+        mix.push((name.to_string(), weight));
+    }
+    Ok(mix)
+}
+
+/// Turn a `voice_mix` object into a mix, ordered by name so equal objects resolve identically
+pub fn mix_from_map(voice_mix: &HashMap<String, f32>) -> Result<Vec<(String, f32)>, String> {
+    let mut mix: Vec<(String, f32)> = voice_mix.iter().map(|(name, w)| (name.clone(), *w)).collect();
+    if mix.is_empty() {
+        return Err("voice_mix must not be empty".to_string());
+    }
+    if mix.iter().any(|(_, w)| !w.is_finite() || *w < 0.0) {
+        return Err("voice_mix weights must be non-negative numbers".to_string());
+    }
+    mix.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(mix)
+}
+
+// ============================================================================
+// Catalogue
+// ============================================================================
+
+#[derive(Serialize, Debug)]
+pub struct VoiceInfo {
+    pub id: String,
+    pub object: &'static str,
+    /// Style the name resolves to (the voice JSON file stem)
+    pub style_id: String,
+    /// Whether `id` is an alias of another style rather than a style itself
+    pub alias: bool,
+    /// Every other name resolving to the same style
+    pub aliases: Vec<String>,
+    pub gender: Option<&'static str>,
+    pub languages: Vec<&'static str>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct VoiceFilter {
+    /// `male` or `female`
+    gender: Option<String>,
+    /// Only voices supporting this language code
+    lang: Option<String>,
+    /// Only names resolving to this style ID
+    style_id: Option<String>,
+    /// Case-insensitive substring of the name or any of its aliases
+    q: Option<String>,
+    /// Include alias entries; defaults to true
+    include_aliases: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct VoiceList {
+    object: &'static str,
+    data: Vec<VoiceInfo>,
+}
+
+/// Gender of the stock Supertonic styles, which are named `F<n>` / `M<n>`
+pub fn style_gender(style_id: &str) -> Option<&'static str> {
+    let mut chars = style_id.chars();
+    match (chars.next(), chars.next()) {
+        (Some('F'), Some(c)) if c.is_ascii_digit() => Some("female"),
+        (Some('M'), Some(c)) if c.is_ascii_digit() => Some("male"),
+        _ => None,
+    }
+}
+
+fn voice_info(registry: &VoiceRegistry, config: &ServerConfig, name: &str) -> VoiceInfo {
+    let style_id = registry.style_id_of(name).to_string();
+    let mut aliases = registry.aliases_of(&style_id);
+    aliases.retain(|alias| alias != name);
+    if registry.is_alias(name) {
+        aliases.insert(0, style_id.clone());
+    }
+
+    VoiceInfo {
+        id: name.to_string(),
+        object: "voice",
+        alias: registry.is_alias(name),
+        aliases,
+        gender: style_gender(&style_id),
+        languages: config.languages_for(Some((name, &style_id))),
+        style_id,
+    }
+}
+
+/// Describe every name in the voice map
+pub fn catalogue(registry: &VoiceRegistry, config: &ServerConfig) -> Vec<VoiceInfo> {
+    let mut names: Vec<&String> = registry
+        .styles
+        .keys()
+        .chain(registry.aliases.keys())
+        .collect();
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| voice_info(registry, config, name))
+        .collect()
+}
+
+impl VoiceFilter {
+    fn matches(&self, voice: &VoiceInfo) -> bool {
+        if !self.include_aliases.unwrap_or(true) && voice.alias {
+            return false;
+        }
+        if let Some(ref gender) = self.gender {
+            if !voice.gender.is_some_and(|g| g.eq_ignore_ascii_case(gender)) {
+                return false;
+            }
+        }
+        if let Some(ref lang) = self.lang {
+            if !voice.languages.contains(&lang.as_str()) {
+                return false;
+            }
+        }
+        if let Some(ref style_id) = self.style_id {
+            if &voice.style_id != style_id {
+                return false;
+            }
+        }
+        if let Some(ref q) = self.q {
+            let q = q.to_lowercase();
+            let hit = std::iter::once(&voice.id)
+                .chain(voice.aliases.iter())
+                .any(|name| name.to_lowercase().contains(&q));
+            if !hit {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub async fn list_voices(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<VoiceFilter>,
+) -> Response {
+    let data = catalogue(&state.voices.read().unwrap(), &state.config)
+        .into_iter()
+        .filter(|voice| filter.matches(voice))
+        .collect();
+
+    Json(VoiceList { object: "list", data }).into_response()
+}
+
+// ============================================================================
+// Registration
+// ============================================================================
+
+#[derive(Deserialize, Debug)]
+pub struct RenameVoiceRequest {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlendVoiceRequest {
+    /// Name to register the blend under
+    name: String,
+    /// Mix in `voice` syntax, e.g. `Sarah:0.7+Lily:0.3`
+    voice: Option<String>,
+    voice_mix: Option<HashMap<String, f32>>,
+}
+
+/// Voice names double as file stems, so keep them to a safe character set
+fn is_valid_voice_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn invalid_request(status: StatusCode, message: &str) -> Response {
+    openai_error(status, message, "invalid_request_error", None, None)
+}
+
+/// `PUT /v1/audio/voices/:name` - create or replace a voice from a `VoiceStyleData` JSON body
+pub async fn upload_voice(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    body: Bytes,
+) -> Response {
+    if !is_valid_voice_name(&name) {
+        return invalid_request(StatusCode::BAD_REQUEST, "Voice names may only contain letters, digits, '_' and '-' (max 64)");
+    }
+    if state.voices.read().unwrap().is_alias(&name) {
+        return invalid_request(StatusCode::CONFLICT, &format!("'{}' is an alias and cannot be replaced", name));
+    }
+
+    register_style_json(&state, &name, &body).await
+}
+
+/// `POST /v1/audio/voices/blend` - register a blend of existing voices as a named voice
+pub async fn register_blend(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<BlendVoiceRequest>,
+) -> Response {
+    let name = payload.name;
+    if !is_valid_voice_name(&name) {
+        return invalid_request(StatusCode::BAD_REQUEST, "Voice names may only contain letters, digits, '_' and '-' (max 64)");
+    }
+
+    let mix = match (payload.voice_mix.as_ref(), payload.voice.as_deref()) {
+        (Some(voice_mix), _) => mix_from_map(voice_mix),
+        (None, Some(voice)) => parse_voice_spec(voice),
+        (None, None) => Err("Either voice or voice_mix is required".to_string()),
+    };
+    let resolved = {
+        let voices = state.voices.read().unwrap();
+        if voices.is_alias(&name) {
+            return invalid_request(StatusCode::CONFLICT, &format!("'{}' is an alias and cannot be replaced", name));
+        }
+        mix.and_then(|mix| voices.resolve(&mix))
+    };
+    let style = match resolved {
+        Ok((style, _)) => style,
+        Err(message) => return invalid_request(StatusCode::BAD_REQUEST, &message),
+    };
+
+    let body = match serde_json::to_vec(&style.to_voice_style_data()) {
+        Ok(body) => body,
+        Err(e) => return invalid_request(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to serialize voice style: {}", e)),
+    };
+    register_style_json(&state, &name, &body).await
+}
+
+/// Validate a voice style JSON, persist it as `<name>.json` and register it
+async fn register_style_json(state: &AppState, name: &str, body: &[u8]) -> Response {
+    // Validate through the regular loader before the file becomes visible under its final name
+    let final_path = state.voice_style_dir.join(format!("{}.json", name));
+    let tmp_path = state
+        .voice_style_dir
+        .join(format!(".{}.{:016x}.upload", name, rand::random::<u64>()));
+    if let Err(e) = tokio::fs::write(&tmp_path, body).await {
+        error!("Failed to write voice upload: {}", e);
+        return invalid_request(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store voice style");
+    }
+
+    let tmp_str = tmp_path.to_string_lossy().to_string();
+    let loaded = tokio::task::spawn_blocking(move || load_voice_style(&[tmp_str], false)).await;
+    let style = match loaded {
+        Ok(Ok(style)) => style,
+        Ok(Err(e)) => {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return invalid_request(StatusCode::BAD_REQUEST, &format!("Invalid voice style: {}", e));
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return invalid_request(StatusCode::INTERNAL_SERVER_ERROR, &format!("Task Error: {}", e));
+        }
+    };
+
+    let shape_mismatch = state.voices.read().unwrap().reference_style().and_then(|reference| {
+        (reference.ttl.shape() != style.ttl.shape() || reference.dp.shape() != style.dp.shape()).then(|| {
+            format!(
+                "Voice style shape ttl {:?} / dp {:?} does not match the loaded voices (ttl {:?} / dp {:?})",
+                style.ttl.shape(), style.dp.shape(), reference.ttl.shape(), reference.dp.shape()
+            )
+        })
+    });
+    if let Some(message) = shape_mismatch {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return invalid_request(StatusCode::BAD_REQUEST, &message);
+    }
+
+    if let Err(e) = tokio::fs::rename(&tmp_path, &final_path).await {
+        error!("Failed to persist voice style {}: {}", name, e);
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return invalid_request(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store voice style");
+    }
+
+    let mut voices = state.voices.write().unwrap();
+    let replaced = voices.insert_style(name.to_string(), style);
+    info!("{} voice style: {}", if replaced { "Replaced" } else { "Registered" }, name);
+
+    let status = if replaced { StatusCode::OK } else { StatusCode::CREATED };
+    (status, Json(voice_info(&voices, &state.config, name))).into_response()
+}
+
+/// `DELETE /v1/audio/voices/:name` - remove a voice style and its file
+pub async fn delete_voice(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Response {
+
+    {
+        let mut voices = state.voices.write().unwrap();
+        if voices.is_alias(&name) {
+            return invalid_request(StatusCode::CONFLICT, &format!("'{}' is an alias and cannot be deleted", name));
+        }
+        let aliases = voices.aliases_of(&name);
+        if !aliases.is_empty() {
+            return invalid_request(
+                StatusCode::CONFLICT,
+                &format!("Voice '{}' is still referenced by aliases: {}", name, aliases.join(", ")),
+            );
+        }
+        if voices.remove_style(&name).is_none() {
+            return invalid_request(StatusCode::NOT_FOUND, &format!("Unknown voice: {}", name));
+        }
+    }
+
+    let path = state.voice_style_dir.join(format!("{}.json", name));
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            error!("Failed to delete voice style file {:?}: {}", path, e);
+            return invalid_request(StatusCode::INTERNAL_SERVER_ERROR, "Voice removed, but its file could not be deleted");
+        }
+    }
+    info!("Deleted voice style: {}", name);
+
+    Json(serde_json::json!({ "id": name, "object": "voice", "deleted": true })).into_response()
+}
+
+/// `POST /v1/audio/voices/:name/rename` - rename a voice style nothing else refers to.
+/// Aliases and `[voices]` entries live in startup config, so renaming under them would
+/// leave them dangling after a restart.
+pub async fn rename_voice(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(payload): Json<RenameVoiceRequest>,
+) -> Response {
+    let new_name = payload.name;
+    if !is_valid_voice_name(&new_name) {
+        return invalid_request(StatusCode::BAD_REQUEST, "Voice names may only contain letters, digits, '_' and '-' (max 64)");
+    }
+
+    {
+        let voices = state.voices.read().unwrap();
+        if voices.is_alias(&name) {
+            return invalid_request(StatusCode::CONFLICT, &format!("'{}' is an alias and cannot be renamed", name));
+        }
+        if !voices.contains(&name) {
+            return invalid_request(StatusCode::NOT_FOUND, &format!("Unknown voice: {}", name));
+        }
+        let aliases = voices.aliases_of(&name);
+        if !aliases.is_empty() {
+            return invalid_request(
+                StatusCode::CONFLICT,
+                &format!("Voice '{}' is still referenced by aliases: {}", name, aliases.join(", ")),
+            );
+        }
+        if state.config.has_voice_defaults(&name) {
+            return invalid_request(
+                StatusCode::CONFLICT,
+                &format!("Voice '{}' has defaults under [voices] in the config file", name),
+            );
+        }
+        if voices.contains(&new_name) {
+            return invalid_request(StatusCode::CONFLICT, &format!("Voice '{}' already exists", new_name));
+        }
+    }
+
+    let from = state.voice_style_dir.join(format!("{}.json", name));
+    let to = state.voice_style_dir.join(format!("{}.json", new_name));
+    if let Err(e) = tokio::fs::rename(&from, &to).await {
+        error!("Failed to rename voice style file {:?}: {}", from, e);
+        return invalid_request(StatusCode::INTERNAL_SERVER_ERROR, "Failed to rename voice style");
+    }
+
+    let mut voices = state.voices.write().unwrap();
+    if !voices.rename_style(&name, &new_name) {
+        // Deleted concurrently; put the file back where it was
+        drop(voices);
+        let _ = std::fs::rename(&to, &from);
+        return invalid_request(StatusCode::NOT_FOUND, &format!("Unknown voice: {}", name));
+    }
+    info!("Renamed voice style {} -> {}", name, new_name);
+
+    Json(voice_info(&voices, &state.config, &new_name)).into_response()
+}
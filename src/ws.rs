@@ -0,0 +1,438 @@
+// ============================================================================
+// Realtime WebSocket - Stream text in, stream PCM audio out
+// ============================================================================
+//
+// Protocol (client -> server, JSON text frames):
+//   {"type": "session.update", "voice": "Sarah", "lang": "en", "speed": 1.0, "total_step": 5}
+//   {"type": "text", "text": "Hello wor"}
+//   {"type": "flush"}
+//   {"type": "cancel"}
+//
+// Server -> client:
+//   JSON text frames: session.created, session.updated, flush.done, cancelled, error
+//   (errors for text dropped by the character rate limit carry "code": "rate_limit_exceeded";
+//   sentences dropped because the work queue is full carry "code": "queue_full" and "retry_after")
+//   Binary frames: 16-bit little-endian mono PCM, one frame per synthesized sentence
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, Query, State,
+    },
+    response::Response,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info};
+
+use crate::audio::samples_to_pcm16;
+use crate::auth::ApiKeyId;
+use crate::helper::{default_seed, ends_sentence, is_valid_lang, split_sentences, CancelToken, Style};
+use crate::ratelimit::ClientLimit;
+use crate::voices::parse_voice_spec;
+use crate::queue::{Priority, QueueFull, WorkQueue};
+use crate::{AppState, CHUNK_SILENCE_SECS};
+
+/// Per-session synthesis settings, also accepted as query parameters on connect
+#[derive(Deserialize, Debug, Default)]
+pub struct SessionParams {
+    voice: Option<String>,
+    lang: Option<String>,
+    speed: Option<f32>,
+    total_step: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum ClientMessage {
+    #[serde(rename = "session.update")]
+    SessionUpdate(SessionParams),
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "flush")]
+    Flush,
+    #[serde(rename = "cancel")]
+    Cancel,
+}
+
+#[derive(Clone, Debug)]
+struct Session {
+    voice: Option<String>,
+    lang: String,
+    speed: f32,
+    total_step: usize,
+}
+
+impl Session {
+    fn apply(&mut self, state: &AppState, params: SessionParams) -> Result<(), String> {
+        let limits = &state.config.limits;
+        if let Some(ref voice) = params.voice {
+            resolve_voice(state, voice)?;
+        }
+        if let Some(ref lang) = params.lang {
+            if !is_valid_lang(lang) {
+                return Err(format!("Invalid language: {}", lang));
+            }
+        }
+        if let Some(speed) = params.speed {
+            if !(limits.min_speed..=limits.max_speed).contains(&speed) {
+                return Err(format!("speed must be between {} and {}", limits.min_speed, limits.max_speed));
+            }
+        }
+        if let Some(total_step) = params.total_step {
+            if !(1..=limits.max_total_step).contains(&total_step) {
+                return Err(format!("total_step must be between 1 and {}", limits.max_total_step));
+            }
+        }
+
+        // The language must suit the voice the session ends up with
+        let voice = params.voice.as_ref().or(self.voice.as_ref());
+        let lang = params.lang.as_ref().unwrap_or(&self.lang);
+        let style_id = voice.map(|voice| state.voices.read().unwrap().style_id_of(voice).to_string());
+        let voice_id = voice.zip(style_id.as_ref()).map(|(voice, style_id)| (voice.as_str(), style_id.as_str()));
+        if !state.config.languages_for(voice_id).contains(&lang.as_str()) {
+            return Err(format!("Language {} is not allowed for this voice", lang));
+        }
+
+        if params.voice.is_some() {
+            self.voice = params.voice;
+        }
+        if let Some(lang) = params.lang {
+            self.lang = lang;
+        }
+        if let Some(speed) = params.speed {
+            self.speed = speed;
+        }
+        if let Some(total_step) = params.total_step {
+            self.total_step = total_step;
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "voice": self.voice,
+            "lang": self.lang,
+            "speed": self.speed,
+            "total_step": self.total_step,
+        })
+    }
+}
+
+/// Work item for the per-socket synthesis worker
+enum Job {
+    Sentence {
+        text: String,
+        session: Session,
+        generation: u64,
+        leading_silence: bool,
+    },
+    /// Marks the end of a flush; acknowledged once every sentence before it is sent
+    FlushDone { generation: u64 },
+}
+
+pub async fn speech_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SessionParams>,
+    client_limit: Option<Extension<ClientLimit>>,
+    key: Option<Extension<ApiKeyId>>,
+) -> Response {
+    let client_limit = client_limit.map(|Extension(limit)| limit);
+    // Sentences take the class of the session's key, like HTTP requests
+    let priority = state.config.priority_for(key.as_ref().map(|Extension(ApiKeyId(name))| name.as_str()));
+    ws.on_upgrade(move |socket| handle_socket(socket, state, params, client_limit, priority))
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: Arc<AppState>,
+    params: SessionParams,
+    client_limit: Option<ClientLimit>,
+    priority: Priority,
+) {
+    // Start from the default voice's configured defaults, if any
+    let style_id = state
+        .default_voice
+        .as_deref()
+        .map(|voice| state.voices.read().unwrap().style_id_of(voice).to_string());
+    let voice_id = state.default_voice.as_deref().zip(style_id.as_deref());
+    let defaults = voice_id.and_then(|(voice, style_id)| state.config.voice_defaults(voice, style_id));
+    let mut session = Session {
+        voice: state.default_voice.clone(),
+        lang: state.config.default_lang_for(voice_id),
+        speed: defaults.and_then(|d| d.speed).unwrap_or(1.0),
+        total_step: defaults
+            .and_then(|d| d.total_step)
+            .unwrap_or(state.default_total_step),
+    };
+    if let Err(message) = session.apply(&state, params) {
+        let _ = socket.send(error_message(&message)).await;
+        return;
+    }
+
+    let sample_rate = state.engines.sample_rate;
+
+    let created = serde_json::json!({
+        "type": "session.created",
+        "sample_rate": sample_rate,
+        "format": "pcm_s16le",
+        "session": session.to_json(),
+    });
+    if socket.send(Message::Text(created.to_string())).await.is_err() {
+        return;
+    }
+
+    // Bumped on cancel; the worker drops queued sentences and cancels the one being synthesized
+    let (generation, generation_rx) = watch::channel(0u64);
+    let (job_tx, job_rx) = mpsc::unbounded_channel::<Job>();
+    let (out_tx, mut out_rx) = mpsc::channel::<Message>(16);
+    tokio::spawn(synthesis_worker(state.clone(), job_rx, out_tx, generation_rx, sample_rate, priority));
+
+    let mut buffer = String::new();
+    // Whether a sentence was already queued since the last flush or cancel
+    let mut in_turn = false;
+
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                let text = match incoming {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(Message::Binary(_))) => {
+                        if socket.send(error_message("Binary input is not supported")).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        info!("WebSocket receive error: {}", e);
+                        break;
+                    }
+                };
+
+                let message = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        if socket.send(error_message(&format!("Invalid message: {}", e))).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+
+                let reply = match message {
+                    ClientMessage::SessionUpdate(params) => match session.apply(&state, params) {
+                        Ok(()) => Some(Message::Text(serde_json::json!({
+                            "type": "session.updated",
+                            "session": session.to_json(),
+                        }).to_string())),
+                        Err(message) => Some(error_message(&message)),
+                    },
+                    ClientMessage::Text { text } => {
+                        // Text over the character rate limit is dropped, not buffered
+                        if let Some(Err(limited)) = client_limit.as_ref().map(|l| l.check_chars(text.chars().count())) {
+                            if socket.send(rate_limit_message(limited.message())).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        buffer.push_str(&text);
+                        let mut sentences = split_sentences(&buffer);
+                        // An unfinished last piece may still be growing; keep it buffered until more text or a flush
+                        let rest = match sentences.last() {
+                            Some(last) if !ends_sentence(last) => sentences.pop().unwrap_or_default(),
+                            _ => String::new(),
+                        };
+                        let current = *generation.borrow();
+                        for sentence in sentences {
+                            queue_sentence(&job_tx, &session, sentence, current, &mut in_turn);
+                        }
+                        buffer = rest;
+                        None
+                    }
+                    ClientMessage::Flush => {
+                        let current = *generation.borrow();
+                        let rest = std::mem::take(&mut buffer);
+                        queue_sentence(&job_tx, &session, rest, current, &mut in_turn);
+                        let _ = job_tx.send(Job::FlushDone { generation: current });
+                        in_turn = false;
+                        None
+                    }
+                    ClientMessage::Cancel => {
+                        generation.send_modify(|current| *current += 1);
+                        buffer.clear();
+                        in_turn = false;
+                        Some(Message::Text(serde_json::json!({ "type": "cancelled" }).to_string()))
+                    }
+                };
+
+                if let Some(reply) = reply {
+                    if socket.send(reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            Some(outgoing) = out_rx.recv() => {
+                if socket.send(outgoing).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Make the worker discard anything still queued or in flight
+    generation.send_modify(|current| *current += 1);
+}
+
+fn queue_sentence(
+    job_tx: &mpsc::UnboundedSender<Job>,
+    session: &Session,
+    sentence: String,
+    generation: u64,
+    in_turn: &mut bool,
+) {
+    let text = sentence.trim().to_string();
+    if text.is_empty() {
+        return;
+    }
+    let _ = job_tx.send(Job::Sentence {
+        text,
+        session: session.clone(),
+        generation,
+        leading_silence: *in_turn,
+    });
+    *in_turn = true;
+}
+
+async fn synthesis_worker(
+    state: Arc<AppState>,
+    mut job_rx: mpsc::UnboundedReceiver<Job>,
+    out_tx: mpsc::Sender<Message>,
+    mut generation: watch::Receiver<u64>,
+    sample_rate: i32,
+    priority: Priority,
+) {
+    while let Some(job) = job_rx.recv().await {
+        let message = match job {
+            Job::FlushDone { generation: job_generation } => {
+                if job_generation != *generation.borrow() {
+                    continue;
+                }
+                Message::Text(serde_json::json!({ "type": "flush.done" }).to_string())
+            }
+            Job::Sentence { text, session, generation: job_generation, leading_silence } => {
+                if job_generation != *generation.borrow_and_update() {
+                    continue;
+                }
+                let Some(voice) = session.voice.clone() else {
+                    if out_tx.send(error_message("No voice selected; send session.update first")).await.is_err() {
+                        return;
+                    }
+                    continue;
+                };
+
+                // The voice may have been deleted since it was selected
+                let (style, voice_name) = match resolve_voice(&state, &voice) {
+                    Ok(resolved) => resolved,
+                    Err(message) => {
+                        if out_tx.send(error_message(&message)).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
+                // Each sentence takes a place in the work queue like an HTTP request
+                let admission = match state.queue.admit(priority, WorkQueue::cost(&text)) {
+                    Ok(admission) => admission,
+                    Err(full) => {
+                        if out_tx.send(queue_full_message(&full)).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
+                let state = state.clone();
+                let cancel = CancelToken::default();
+                let token = cancel.clone();
+                let handle = tokio::task::spawn_blocking(move || {
+                    let _admission = admission;
+                    let mut tts = state.engines.checkout(priority);
+                    let started = Instant::now();
+                    // Seeded like an HTTP request without `seed`, so sentences share the sentence cache
+                    let seed = default_seed(&voice_name, session.speed, session.total_step, &session.lang);
+                    let result = tts.call(&text, &session.lang, &style, session.total_step, session.speed, CHUNK_SILENCE_SECS, seed, &token);
+                    if let Ok((_, dur)) = result {
+                        state.engines.observe_synthesis(started.elapsed(), dur, text.chars().count());
+                    }
+                    result
+                });
+
+                // Barge-in or a closed socket stops the sentence at its next denoising step
+                let result = tokio::select! {
+                    result = handle => result,
+                    _ = generation.changed() => {
+                        cancel.cancel();
+                        continue;
+                    }
+                    _ = out_tx.closed() => {
+                        cancel.cancel();
+                        return;
+                    }
+                };
+
+                match result {
+                    Ok(Ok((wav, _))) => {
+                        let mut samples = Vec::new();
+                        if leading_silence {
+                            samples.resize((CHUNK_SILENCE_SECS * sample_rate as f32) as usize, 0.0);
+                        }
+                        samples.extend(wav);
+                        Message::Binary(samples_to_pcm16(&samples))
+                    }
+                    Ok(Err(e)) => error_message(&format!("TTS Error: {}", e)),
+                    Err(e) => {
+                        error!("WebSocket synthesis task failed: {}", e);
+                        error_message(&format!("Task Error: {}", e))
+                    }
+                }
+            }
+        };
+
+        if out_tx.send(message).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Resolve a voice name or mix (`Sarah:0.7+Lily:0.3`) against the current registry
+fn resolve_voice(state: &AppState, voice: &str) -> Result<(Arc<Style>, String), String> {
+    let mix = parse_voice_spec(voice)?;
+    state.voices.read().unwrap().resolve(&mix)
+}
+
+fn error_message(message: &str) -> Message {
+    Message::Text(serde_json::json!({ "type": "error", "message": message }).to_string())
+}
+
+fn rate_limit_message(message: &str) -> Message {
+    Message::Text(serde_json::json!({
+        "type": "error",
+        "code": "rate_limit_exceeded",
+        "message": message,
+    }).to_string())
+}
+
+fn queue_full_message(full: &QueueFull) -> Message {
+    Message::Text(serde_json::json!({
+        "type": "error",
+        "code": "queue_full",
+        "message": full.message(),
+        "retry_after": full.retry_after_secs(),
+    }).to_string())
+}